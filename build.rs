@@ -0,0 +1,24 @@
+use std::{env, process::Command};
+
+/// Embeds the git commit and rustc version for `dotfiles version --verbose`.
+fn main() {
+    let git_hash = command_output("git", &["rev-parse", "--short", "HEAD"]);
+    let rustc = env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    let rustc_version = command_output(&rustc, &["--version"]);
+
+    println!("cargo:rustc-env=GIT_HASH={}", git_hash);
+    println!("cargo:rustc-env=RUSTC_VERSION={}", rustc_version);
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs");
+}
+
+fn command_output(program: &str, args: &[&str]) -> String {
+    Command::new(program)
+        .args(args)
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|output| output.trim().to_string())
+        .unwrap_or_else(|| "unknown".to_string())
+}
@@ -0,0 +1,1138 @@
+use anyhow::{Context, Result};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    env,
+    path::{Path, PathBuf},
+};
+
+#[derive(Deserialize, Serialize, JsonSchema, Debug)]
+pub struct FileMapping {
+    /// Lets other mappings build on this one's target with `${name.target}`.
+    #[serde(default)]
+    pub name: Option<String>,
+    /// A path, or a glob (`*` and `?`) whose matches are each linked into
+    /// the target directory by name.
+    pub source: String,
+    /// Where the source goes, relative to home. A trailing `/` means inside
+    /// that directory under the source's name; otherwise it's the exact path.
+    pub target: Targets,
+    /// Disabled mappings stay in the config but are skipped by install.
+    #[serde(default)]
+    pub enabled: Option<bool>,
+    /// Render the source through the template engine and write the result,
+    /// instead of linking it.
+    #[serde(default)]
+    pub template: bool,
+    /// Join every file in the source directory, sorted by path, into the
+    /// target file instead of linking them.
+    #[serde(default)]
+    pub concat: bool,
+    /// Set to false to replace existing targets without backing them up.
+    #[serde(default)]
+    pub backup: Option<bool>,
+    /// Whether targets are symlinks to the source (the default) or copies.
+    #[serde(default)]
+    pub strategy: Option<Strategy>,
+    /// Permissions for copied and rendered files, e.g. `0o600`.
+    #[serde(default)]
+    pub mode: Option<u32>,
+    /// Only install on these operating systems (as in `std::env::consts::OS`,
+    /// e.g. `linux`, `macos`).
+    #[serde(default)]
+    pub os: Option<Vec<String>>,
+    /// Remove write permission from copied or generated targets so they are
+    /// edited in the repo rather than in place. Has no effect on links.
+    #[serde(default)]
+    pub readonly: bool,
+    /// Allow targets that resolve outside the home directory, such as
+    /// absolute paths.
+    #[serde(default)]
+    pub allow_outside_home: bool,
+    /// How many levels of subdirectories of a directory source to walk.
+    /// Deeper directories are linked as a whole (or skipped when copying).
+    #[serde(default)]
+    pub max_depth: Option<usize>,
+    /// Shell command that must exit 0 for the mapping to apply, e.g.
+    /// `command -v nvidia-smi`. It should only inspect the system, since it
+    /// also runs for `--dry-run` and `status`.
+    #[serde(default)]
+    pub when: Option<String>,
+    /// New names for files inside a directory source, e.g.
+    /// `{ zshrc = ".zshrc" }`, so repo files needn't be hidden.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub rename: BTreeMap<String, String>,
+    /// Only create targets that don't exist yet, leaving anything already
+    /// there alone, for seed-once files edited per machine.
+    #[serde(default)]
+    pub skip_if_exists: bool,
+}
+
+/// How a mapping's files are put in place.
+#[derive(Deserialize, Serialize, JsonSchema, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Strategy {
+    #[default]
+    Link,
+    Copy,
+}
+
+/// The `[defaults]` section: values for any mapping that doesn't set them.
+#[derive(Deserialize, Serialize, JsonSchema, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Defaults {
+    pub strategy: Option<Strategy>,
+    pub backup: Option<bool>,
+    pub mode: Option<u32>,
+    pub os: Option<Vec<String>>,
+}
+
+/// A mapping's target: either a single path or a list of paths that all link
+/// to the same source. A path ending in `/` names the directory to place the
+/// source in (`.config/nvim/` puts `init.lua` at `.config/nvim/init.lua`);
+/// otherwise it is the exact path the source ends up at.
+#[derive(Deserialize, Serialize, JsonSchema, Debug, Clone)]
+#[serde(untagged)]
+pub enum Targets {
+    One(String),
+    Many(Vec<String>),
+}
+
+/// Syntax a config file is written in.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ConfigFormat {
+    #[default]
+    Toml,
+    Json,
+    Yaml,
+}
+
+impl ConfigFormat {
+    /// The format implied by a file's extension, if it has a known one.
+    pub fn from_extension(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "toml" => Some(Self::Toml),
+            "json" => Some(Self::Json),
+            "yaml" | "yml" => Some(Self::Yaml),
+            _ => None,
+        }
+    }
+
+    /// Writes `value` out in this syntax. Unset optional fields are left
+    /// out, as TOML has no null.
+    pub fn render(self, value: &impl Serialize) -> Result<String> {
+        let value = toml::Value::try_from(value)?;
+        Ok(match self {
+            Self::Toml => toml::to_string(&value)?,
+            Self::Json => serde_json::to_string_pretty(&value)? + "\n",
+            Self::Yaml => serde_yaml::to_string(&value)?,
+        })
+    }
+
+    /// Parses `contents` into a TOML table, whatever the source syntax.
+    pub fn parse(self, contents: &str) -> Result<toml::Table> {
+        Ok(match self {
+            Self::Toml => toml::from_str(contents)?,
+            Self::Json => serde_json::from_str(contents)?,
+            Self::Yaml => serde_yaml::from_str(contents)?,
+        })
+    }
+}
+
+/// Operating system names mappings can be limited to, as reported by
+/// `std::env::consts::OS`.
+pub const KNOWN_OS: &[&str] = &[
+    "linux",
+    "macos",
+    "windows",
+    "freebsd",
+    "openbsd",
+    "netbsd",
+    "dragonfly",
+    "solaris",
+    "illumos",
+    "android",
+    "ios",
+];
+
+/// The newest config layout this binary understands. Bump it when a config
+/// change needs older binaries to refuse the file instead of misreading it.
+pub const SCHEMA_VERSION: u32 = 1;
+
+#[derive(Deserialize, Serialize, JsonSchema, Debug)]
+pub struct Config {
+    /// Layout version the config was written for; assumed current if unset.
+    #[serde(default)]
+    pub schema_version: Option<u32>,
+    #[serde(default)]
+    pub files: Vec<FileMapping>,
+    /// Directories searched in order for each mapping's source, so a private
+    /// overlay can shadow files in a shared repo. Relative paths are within
+    /// the dotfiles directory, which is searched last unless listed.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub source_roots: Vec<String>,
+    /// Mappings grouped by topic; merged into `files` when the config loads.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub topics: BTreeMap<String, Topic>,
+    /// Values available to templated files.
+    #[serde(default)]
+    pub vars: BTreeMap<String, String>,
+    #[serde(default)]
+    pub secrets: Secrets,
+    #[serde(default)]
+    pub defaults: Defaults,
+    #[serde(default)]
+    pub hooks: Hooks,
+}
+
+/// The `[hooks]` section: shell commands run around install.
+#[derive(Deserialize, Serialize, JsonSchema, Debug, Default)]
+pub struct Hooks {
+    /// Run in order after every mapping is installed.
+    #[serde(default)]
+    pub post_install: Vec<String>,
+}
+
+/// A `[topics.<name>]` section.
+#[derive(Deserialize, Serialize, JsonSchema, Debug, Default)]
+pub struct Topic {
+    /// Directory in the repo this topic's sources are relative to, so
+    /// `source = "init.lua"` can stand for `nvim/init.lua`.
+    pub source_dir: Option<String>,
+    #[serde(default)]
+    pub files: Vec<FileMapping>,
+}
+
+/// The `[secrets]` section.
+#[derive(Deserialize, Serialize, JsonSchema, Debug, Default)]
+pub struct Secrets {
+    /// Set to false to stop install and status checking for secrets.
+    #[serde(default)]
+    pub enabled: Option<bool>,
+    /// Shell command whose stdout becomes the secrets file at install time.
+    pub command: Option<String>,
+    /// Where the secrets file lives, relative to home. Defaults to `.secrets`.
+    pub target: Option<String>,
+    /// Secret files that must exist after install, like `~/.config/app/token`.
+    /// Defaults to just the secrets file.
+    #[serde(default)]
+    pub required: Vec<String>,
+    /// A `KEY=value` file, relative to home, whose variables are passed to
+    /// hooks.
+    pub env_file: Option<String>,
+}
+
+impl Secrets {
+    pub fn target(&self) -> &str {
+        self.target.as_deref().unwrap_or(".secrets")
+    }
+
+    /// Every required secret path, resolved against `home_dir`.
+    pub fn required_paths(&self, home_dir: &Path) -> Vec<PathBuf> {
+        if self.required.is_empty() {
+            return vec![home_dir.join(self.target())];
+        }
+        self.required
+            .iter()
+            .map(|path| home_path(home_dir, path))
+            .collect()
+    }
+
+    /// The `env_file`, resolved against `home_dir`.
+    pub fn env_file_path(&self, home_dir: &Path) -> Option<PathBuf> {
+        self.env_file
+            .as_deref()
+            .map(|path| home_path(home_dir, path))
+    }
+}
+
+/// Resolves a config path against home, allowing a leading `~/`.
+fn home_path(home_dir: &Path, path: &str) -> PathBuf {
+    match path.strip_prefix("~/") {
+        Some(relative) => home_dir.join(relative),
+        None => home_dir.join(path),
+    }
+}
+
+/// Parses a dotenv-style file: `KEY=value` lines, optionally prefixed with
+/// `export` and with the value in matching quotes. Blank lines and lines
+/// starting with `#` are skipped.
+pub fn parse_env_file(contents: &str) -> Result<Vec<(String, String)>> {
+    let mut vars = Vec::new();
+    for (index, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let Some((key, value)) = line.split_once('=') else {
+            anyhow::bail!("Line {} is not KEY=value", index + 1);
+        };
+        let key = key.trim();
+        if key.is_empty() || key.contains(char::is_whitespace) {
+            anyhow::bail!("Line {} has an invalid variable name", index + 1);
+        }
+        let value = value.trim();
+        let value = ['"', '\'']
+            .iter()
+            .find_map(|quote| value.strip_prefix(*quote)?.strip_suffix(*quote))
+            .unwrap_or(value);
+        vars.push((key.to_string(), value.to_string()));
+    }
+    Ok(vars)
+}
+
+impl FileMapping {
+    /// Whether install should touch this mapping: it isn't disabled and, if
+    /// it is limited to some operating systems, this is one of them.
+    pub fn is_enabled(&self) -> bool {
+        self.is_enabled_on(env::consts::OS, &[])
+    }
+
+    /// Like [`FileMapping::is_enabled`], for a chosen OS. Mappings limited to
+    /// any of the `excluded` operating systems are skipped as well.
+    pub fn is_enabled_on(&self, os: &str, excluded: &[String]) -> bool {
+        self.enabled.unwrap_or(true)
+            && self.os.as_ref().is_none_or(|targets| {
+                targets.iter().any(|target| target == os)
+                    && !targets.iter().any(|target| excluded.contains(target))
+            })
+    }
+
+    pub fn strategy(&self) -> Strategy {
+        self.strategy.unwrap_or_default()
+    }
+
+    /// The topic a mapping belongs to: the first path segment of its source.
+    pub fn topic(&self) -> &str {
+        self.source
+            .split(['/', '\\'])
+            .find(|segment| !segment.is_empty())
+            .unwrap_or(&self.source)
+    }
+
+    /// The source's own name, used when a target names its containing
+    /// directory.
+    pub fn source_name(&self) -> &str {
+        self.source
+            .trim_end_matches(['/', '\\'])
+            .rsplit(['/', '\\'])
+            .next()
+            .unwrap_or(&self.source)
+    }
+
+    /// Whether the source is a glob rather than a path.
+    pub fn is_glob(&self) -> bool {
+        self.source.contains(['*', '?'])
+    }
+
+    /// The part of the source before its first glob segment, which has to
+    /// exist before anything can match.
+    pub fn source_base(&self) -> PathBuf {
+        Path::new(&self.source)
+            .components()
+            .take_while(|component| !component.as_os_str().to_string_lossy().contains(['*', '?']))
+            .collect()
+    }
+
+    pub fn targets(&self) -> Vec<&str> {
+        match &self.target {
+            Targets::One(target) => vec![target.as_str()],
+            Targets::Many(targets) => targets.iter().map(String::as_str).collect(),
+        }
+    }
+}
+
+impl Config {
+    /// Moves every `[topics.*]` mapping into `files`, resolving its source
+    /// against the topic's `source_dir`. Sources already starting with the
+    /// `source_dir` are kept as they are.
+    pub fn flatten_topics(&mut self) {
+        for topic in std::mem::take(&mut self.topics).into_values() {
+            for mut mapping in topic.files {
+                if let Some(dir) = &topic.source_dir {
+                    let dir = dir.trim_end_matches(['/', '\\']);
+                    if !mapping.source.starts_with(&format!("{}/", dir)) {
+                        mapping.source = format!("{}/{}", dir, mapping.source);
+                    }
+                }
+                self.files.push(mapping);
+            }
+        }
+    }
+
+    /// Expands `${name.target}` in targets to the target of the mapping with
+    /// that `name`, so related paths can be written relative to each other.
+    pub fn resolve_target_refs(&mut self) -> Result<()> {
+        let mut names = HashMap::new();
+        for (index, mapping) in self.files.iter().enumerate() {
+            if let Some(name) = &mapping.name {
+                if names.insert(name.as_str(), index).is_some() {
+                    anyhow::bail!("More than one mapping is named {}", name);
+                }
+            }
+        }
+
+        let mut resolved = vec![None; self.files.len()];
+        for index in 0..self.files.len() {
+            resolve_refs(index, &self.files, &names, &mut resolved, &mut Vec::new())?;
+        }
+        for (mapping, targets) in self.files.iter_mut().zip(resolved) {
+            let mut targets = targets.unwrap_or_default();
+            mapping.target = match mapping.target {
+                Targets::One(_) => Targets::One(targets.remove(0)),
+                Targets::Many(_) => Targets::Many(targets),
+            };
+        }
+        Ok(())
+    }
+
+    /// Normalizes every target in place (see [`normalize_target`]) and
+    /// returns the `(original, normalized)` pairs that changed.
+    pub fn normalize_targets(&mut self) -> Result<Vec<(String, String)>> {
+        let mut changed = Vec::new();
+        for mapping in &mut self.files {
+            let targets = match &mut mapping.target {
+                Targets::One(target) => std::slice::from_mut(target),
+                Targets::Many(targets) => targets.as_mut_slice(),
+            };
+            for target in targets {
+                let normalized = normalize_target(target)
+                    .with_context(|| format!("Invalid target for {}", mapping.source))?;
+                if normalized != *target {
+                    changed.push((std::mem::replace(target, normalized.clone()), normalized));
+                }
+            }
+        }
+        Ok(changed)
+    }
+
+    /// Rejects configs written for a newer schema than this binary knows.
+    pub fn check_schema_version(&self) -> Result<()> {
+        match self.schema_version {
+            Some(version) if version > SCHEMA_VERSION => anyhow::bail!(
+                "Config schema version {} is newer than this binary supports ({}); upgrade dotfiles",
+                version,
+                SCHEMA_VERSION
+            ),
+            _ => Ok(()),
+        }
+    }
+
+    /// Switches the mappings with these sources (`--copy-dir`) to
+    /// `strategy = "copy"`.
+    pub fn copy_sources(&mut self, sources: &[String]) -> Result<()> {
+        for source in sources {
+            let mapping = self
+                .files
+                .iter_mut()
+                .find(|mapping| {
+                    mapping.source.trim_end_matches('/') == source.trim_end_matches('/')
+                })
+                .with_context(|| format!("--copy-dir {}: no mapping has that source", source))?;
+            mapping.strategy = Some(Strategy::Copy);
+        }
+        Ok(())
+    }
+
+    /// Fills in every mapping field left unset from `[defaults]`.
+    pub fn apply_defaults(&mut self) {
+        let defaults = &self.defaults;
+        for mapping in &mut self.files {
+            mapping.strategy = mapping.strategy.or(defaults.strategy);
+            mapping.backup = mapping.backup.or(defaults.backup);
+            mapping.mode = mapping.mode.or(defaults.mode);
+            if mapping.os.is_none() {
+                mapping.os = defaults.os.clone();
+            }
+        }
+    }
+}
+
+/// Collapses repeated separators and `.` segments in a target and drops a
+/// leading `~/` (targets are relative to home anyway), so equivalent
+/// spellings compare equal. A trailing `/` is kept since it means "inside this
+/// directory". `..` segments are rejected outright.
+pub fn normalize_target(target: &str) -> Result<String> {
+    let mut segments = Vec::new();
+    let relative = target.strip_prefix("~/").unwrap_or(target);
+    for segment in relative.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => anyhow::bail!("Target {} must not contain `..`", target),
+            segment => segments.push(segment),
+        }
+    }
+    if segments.is_empty() {
+        anyhow::bail!("Target {:?} is empty", target);
+    }
+
+    let mut joined = segments.join("/");
+    if target.starts_with('/') {
+        joined.insert(0, '/');
+    }
+    if target.ends_with('/') || target.ends_with("/.") {
+        joined.push('/');
+    }
+    Ok(joined)
+}
+
+/// Fills in `resolved[index]` with the mapping's targets after expanding
+/// references, resolving the mappings it refers to first. `stack` holds the
+/// mappings being resolved, to catch cycles.
+fn resolve_refs(
+    index: usize,
+    files: &[FileMapping],
+    names: &HashMap<&str, usize>,
+    resolved: &mut Vec<Option<Vec<String>>>,
+    stack: &mut Vec<usize>,
+) -> Result<()> {
+    if resolved[index].is_some() {
+        return Ok(());
+    }
+    let label = |index: usize| {
+        let mapping: &FileMapping = &files[index];
+        mapping
+            .name
+            .clone()
+            .unwrap_or_else(|| mapping.source.clone())
+    };
+    if let Some(start) = stack.iter().position(|&seen| seen == index) {
+        let cycle: Vec<String> = stack[start..]
+            .iter()
+            .chain([&index])
+            .map(|&index| label(index))
+            .collect();
+        anyhow::bail!("Circular target reference: {}", cycle.join(" -> "));
+    }
+    stack.push(index);
+
+    let mut targets = Vec::new();
+    for target in files[index].targets() {
+        let mut expanded = String::new();
+        let mut rest = target;
+        while let Some(start) = rest.find("${") {
+            expanded.push_str(&rest[..start]);
+            let after_open = &rest[start + 2..];
+            let end = after_open
+                .find('}')
+                .with_context(|| format!("Unterminated reference in target {}", target))?;
+            let reference = &after_open[..end];
+            let name = reference.strip_suffix(".target").with_context(|| {
+                format!(
+                    "Target {} has an unsupported reference ${{{}}} (expected ${{name.target}})",
+                    target, reference
+                )
+            })?;
+            let &other = names.get(name).with_context(|| {
+                format!(
+                    "Target {} of {} references unknown mapping {}",
+                    target,
+                    label(index),
+                    name
+                )
+            })?;
+            resolve_refs(other, files, names, resolved, stack)?;
+            let [base] = resolved[other].as_deref().unwrap_or_default() else {
+                anyhow::bail!(
+                    "Mapping {} has more than one target, so ${{{}.target}} is ambiguous",
+                    name,
+                    name
+                );
+            };
+            expanded.push_str(base.trim_end_matches('/'));
+            rest = &after_open[end + 1..];
+        }
+        expanded.push_str(rest);
+        targets.push(expanded);
+    }
+
+    stack.pop();
+    resolved[index] = Some(targets);
+    Ok(())
+}
+
+/// Every location a config file may live, in the order they are searched.
+pub fn candidate_paths(
+    explicit: Option<&Path>,
+    dotfiles_dir: &Path,
+    home_dir: &Path,
+) -> Vec<PathBuf> {
+    let mut candidates = Vec::new();
+
+    if let Some(path) = explicit {
+        candidates.push(path.to_path_buf());
+    }
+    if let Some(path) = env::var_os("DOTFILES_CONFIG").filter(|path| !path.is_empty()) {
+        candidates.push(PathBuf::from(path));
+    }
+    candidates.push(dotfiles_dir.join("config.toml"));
+
+    let xdg_config = env::var_os("XDG_CONFIG_HOME")
+        .filter(|path| !path.is_empty())
+        .map(PathBuf::from)
+        .unwrap_or_else(|| home_dir.join(".config"));
+    candidates.push(xdg_config.join("dotfiles").join("config.toml"));
+
+    candidates.push(home_dir.join(".dotfiles.toml"));
+    candidates
+}
+
+/// Flips the `enabled` flag of the mapping whose source is `source`, keeping
+/// the rest of the document (comments, ordering) untouched.
+pub fn set_mapping_enabled(
+    doc: &mut toml_edit::DocumentMut,
+    source: &str,
+    enabled: bool,
+) -> Result<()> {
+    let files = doc
+        .get_mut("files")
+        .and_then(|files| files.as_array_of_tables_mut())
+        .context("Config has no [[files]] entries")?;
+
+    let mapping = files
+        .iter_mut()
+        .find(|table| table.get("source").and_then(|s| s.as_str()) == Some(source))
+        .with_context(|| format!("No mapping with source {}", source))?;
+
+    if enabled {
+        mapping.remove("enabled");
+    } else {
+        mapping["enabled"] = toml_edit::value(false);
+    }
+    Ok(())
+}
+
+/// Appends a `[[files]]` mapping from `source` to `target`, keeping the rest
+/// of the document untouched. Fails if a mapping for `source` already exists.
+pub fn add_mapping(doc: &mut toml_edit::DocumentMut, source: &str, target: &str) -> Result<()> {
+    let files = doc
+        .entry("files")
+        .or_insert_with(|| toml_edit::Item::ArrayOfTables(toml_edit::ArrayOfTables::new()))
+        .as_array_of_tables_mut()
+        .context("`files` in config is not an array of tables")?;
+
+    if files
+        .iter()
+        .any(|table| table.get("source").and_then(|s| s.as_str()) == Some(source))
+    {
+        anyhow::bail!("A mapping with source {} already exists", source);
+    }
+
+    let mut mapping = toml_edit::Table::new();
+    mapping["source"] = toml_edit::value(source);
+    mapping["target"] = toml_edit::value(target);
+    files.push(mapping);
+    Ok(())
+}
+
+/// Drops the mappings for `source` from `[[files]]`, and those for `file` from
+/// `[topics.<topic>]`, where sources are relative to the topic. Returns how
+/// many were removed.
+pub fn remove_mappings(
+    doc: &mut toml_edit::DocumentMut,
+    source: &str,
+    topic: &str,
+    file: &str,
+) -> usize {
+    let mut removed = 0;
+    let mut retain = |files: &mut toml_edit::ArrayOfTables, wanted: &[&str]| {
+        let before = files.len();
+        files.retain(|table| {
+            table
+                .get("source")
+                .and_then(|s| s.as_str())
+                .is_none_or(|s| !wanted.contains(&s.trim_end_matches('/')))
+        });
+        removed += before - files.len();
+    };
+    if let Some(files) = doc
+        .get_mut("files")
+        .and_then(|files| files.as_array_of_tables_mut())
+    {
+        retain(files, &[source]);
+    }
+    if let Some(files) = doc
+        .get_mut("topics")
+        .and_then(|topics| topics.get_mut(topic))
+        .and_then(|topic| topic.get_mut("files"))
+        .and_then(|files| files.as_array_of_tables_mut())
+    {
+        retain(files, &[file, source]);
+    }
+    removed
+}
+
+/// Removes `[topics.*]` tables that no longer hold any mappings.
+pub fn prune_empty_topics(doc: &mut toml_edit::DocumentMut) {
+    let Some(topics) = doc.get_mut("topics").and_then(|t| t.as_table_like_mut()) else {
+        return;
+    };
+    let empty: Vec<String> = topics
+        .iter()
+        .filter(|(_, topic)| {
+            topic
+                .get("files")
+                .and_then(|files| files.as_array_of_tables())
+                .is_none_or(|files| files.is_empty())
+        })
+        .map(|(name, _)| name.to_string())
+        .collect();
+    for name in empty {
+        topics.remove(&name);
+    }
+    if topics.is_empty() {
+        doc.remove("topics");
+    }
+}
+
+/// A config document holding just the given `(source, target)` mappings,
+/// parsed like any other so they're resolved the same way.
+pub fn inline_config(mappings: &[(String, String)]) -> toml::Table {
+    let files = mappings
+        .iter()
+        .map(|(source, target)| {
+            let mut mapping = toml::Table::new();
+            mapping.insert("source".into(), toml::Value::String(source.clone()));
+            mapping.insert("target".into(), toml::Value::String(target.clone()));
+            toml::Value::Table(mapping)
+        })
+        .collect();
+    let mut config = toml::Table::new();
+    config.insert("files".into(), toml::Value::Array(files));
+    config
+}
+
+/// Every key in a parsed config that no config field reads, such as a
+/// `targt` typo, described with where it is. Known keys come from the
+/// config's JSON schema, so new fields are picked up automatically.
+pub fn unknown_keys(config: &toml::Table) -> Vec<String> {
+    let schema = schemars::schema_for!(Config);
+    let schema = schema.as_value();
+    let mut unknown = Vec::new();
+    check_keys(
+        &toml::Value::Table(config.clone()),
+        schema,
+        schema,
+        "",
+        &mut unknown,
+    );
+    unknown
+}
+
+fn check_keys(
+    value: &toml::Value,
+    schema: &serde_json::Value,
+    root: &serde_json::Value,
+    path: &str,
+    unknown: &mut Vec<String>,
+) {
+    let schema = resolve_schema(schema, root);
+    match value {
+        toml::Value::Table(table) => {
+            if let Some(properties) = schema.get("properties").and_then(|p| p.as_object()) {
+                for (key, child) in table {
+                    let child_path = join_key(path, key);
+                    match properties.get(key) {
+                        Some(child_schema) => {
+                            check_keys(child, child_schema, root, &child_path, unknown)
+                        }
+                        None => unknown.push(match table.get("source").and_then(|s| s.as_str()) {
+                            Some(source) => format!(
+                                "unknown key `{}` in the mapping for {} ({})",
+                                key, source, child_path
+                            ),
+                            None => format!("unknown key `{}`", child_path),
+                        }),
+                    }
+                }
+            } else if let Some(values) = schema.get("additionalProperties") {
+                for (key, child) in table {
+                    check_keys(child, values, root, &join_key(path, key), unknown);
+                }
+            }
+        }
+        toml::Value::Array(items) => {
+            if let Some(item_schema) = schema.get("items") {
+                for (index, item) in items.iter().enumerate() {
+                    let item_path = format!("{}[{}]", path, index);
+                    check_keys(item, item_schema, root, &item_path, unknown);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Follows `$ref`s, and picks the table-shaped branch of an `anyOf` (how
+/// optional sections and untagged enums appear in the schema).
+fn resolve_schema<'a>(
+    schema: &'a serde_json::Value,
+    root: &'a serde_json::Value,
+) -> &'a serde_json::Value {
+    if let Some(reference) = schema.get("$ref").and_then(|r| r.as_str()) {
+        let found = reference
+            .strip_prefix("#/$defs/")
+            .and_then(|name| root.get("$defs")?.get(name));
+        if let Some(found) = found {
+            return resolve_schema(found, root);
+        }
+    }
+    for combinator in ["anyOf", "oneOf"] {
+        if let Some(branches) = schema.get(combinator).and_then(|b| b.as_array()) {
+            if let Some(branch) = branches
+                .iter()
+                .map(|b| resolve_schema(b, root))
+                .find(|b| b.get("properties").is_some() || b.get("additionalProperties").is_some())
+            {
+                return branch;
+            }
+        }
+    }
+    schema
+}
+
+fn join_key(path: &str, key: &str) -> String {
+    if path.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}.{}", path, key)
+    }
+}
+
+/// Applies a `key.path=value` override onto a parsed config document.
+/// Keys are checked against the config's schema, the same one
+/// `--strict-config` uses, and a number picks an entry of a list
+/// (`files.0.strategy`). Values are strings where the key only takes a
+/// string, comma-separated lists where it takes a list, and otherwise
+/// booleans or integers when they look like one.
+pub fn apply_override(config: &mut toml::Table, assignment: &str) -> Result<()> {
+    let (key, value) = assignment
+        .split_once('=')
+        .with_context(|| format!("Override {} is not of the form key=value", assignment))?;
+    let key = key.trim();
+    let path: Vec<&str> = key.split('.').collect();
+    if path.iter().any(|part| part.is_empty()) {
+        anyhow::bail!("Unknown config key {}", key);
+    }
+
+    let schema = schemars::schema_for!(Config);
+    let root = schema.as_value();
+    let mut field = root;
+    for part in &path {
+        let current = resolve_schema(field, root);
+        let child = match part.parse::<usize>() {
+            Ok(_) => current.get("items"),
+            Err(_) => current
+                .get("properties")
+                .and_then(|properties| properties.get(*part))
+                .or_else(|| {
+                    current
+                        .get("additionalProperties")
+                        .filter(|v| v.is_object())
+                }),
+        };
+        field = child.with_context(|| format!("Unknown config key {}", key))?;
+    }
+    let types = schema_types(field, root);
+    let is_table = |schema: &serde_json::Value| {
+        let schema = resolve_schema(schema, root);
+        schema.get("properties").is_some() || schema.get("additionalProperties").is_some()
+    };
+    let items_are_tables =
+        types.contains(&"array") && field_items(field, root).is_some_and(is_table);
+    if is_table(field) || items_are_tables {
+        anyhow::bail!(
+            "Config key {} is a table; set one of the keys below it",
+            key
+        );
+    }
+
+    let takes_string = types.contains(&"string");
+    let value = if types.contains(&"array") && (!takes_string || value.contains(',')) {
+        toml::Value::Array(
+            value
+                .split(',')
+                .map(|item| toml::Value::String(item.trim().to_string()))
+                .collect(),
+        )
+    } else if takes_string && !types.iter().any(|t| matches!(*t, "boolean" | "integer")) {
+        toml::Value::String(value.to_string())
+    } else {
+        parse_override_value(value)
+    };
+
+    let (last, parents) = path.split_last().expect("split always yields a key");
+    let Some((first, rest)) = parents.split_first() else {
+        config.insert(last.to_string(), value);
+        return Ok(());
+    };
+    let next = rest.first().unwrap_or(last);
+    let mut node = config
+        .entry(first.to_string())
+        .or_insert_with(|| empty_container(next));
+    for (index, part) in rest.iter().enumerate() {
+        let next = rest.get(index + 1).unwrap_or(last);
+        node = override_entry(node, part, next, key)?;
+    }
+    match node {
+        toml::Value::Table(table) => {
+            table.insert(last.to_string(), value);
+        }
+        node => *override_entry(node, last, last, key)? = value,
+    }
+    Ok(())
+}
+
+/// The entry `part` names in `node`, an index into a list or a key of a
+/// table. Missing table keys are created, holding a list when `next` is an
+/// index; lists never grow, so an index past the end is an error.
+fn override_entry<'a>(
+    node: &'a mut toml::Value,
+    part: &str,
+    next: &str,
+    key: &str,
+) -> Result<&'a mut toml::Value> {
+    match node {
+        toml::Value::Array(items) => {
+            let count = items.len();
+            let index: usize = part
+                .parse()
+                .with_context(|| format!("Cannot set {}: `{}` is not an index", key, part))?;
+            items.get_mut(index).with_context(|| {
+                format!(
+                    "Cannot set {}: there is no entry {} (the list has {})",
+                    key, index, count
+                )
+            })
+        }
+        toml::Value::Table(table) => Ok(table
+            .entry(part.to_string())
+            .or_insert_with(|| empty_container(next))),
+        _ => anyhow::bail!("Cannot set {}: `{}` in config is not a table", key, part),
+    }
+}
+
+fn empty_container(next: &str) -> toml::Value {
+    match next.parse::<usize>() {
+        Ok(_) => toml::Value::Array(Vec::new()),
+        Err(_) => toml::Value::Table(toml::Table::new()),
+    }
+}
+
+/// Every JSON type a schema allows, through `$ref`s and `anyOf`s.
+fn schema_types<'a>(schema: &'a serde_json::Value, root: &'a serde_json::Value) -> Vec<&'a str> {
+    if let Some(reference) = schema.get("$ref").and_then(|r| r.as_str()) {
+        return reference
+            .strip_prefix("#/$defs/")
+            .and_then(|name| root.get("$defs")?.get(name))
+            .map(|found| schema_types(found, root))
+            .unwrap_or_default();
+    }
+    let mut types: Vec<&str> = match schema.get("type") {
+        Some(serde_json::Value::String(name)) => vec![name.as_str()],
+        Some(serde_json::Value::Array(names)) => names.iter().filter_map(|n| n.as_str()).collect(),
+        _ => Vec::new(),
+    };
+    for combinator in ["anyOf", "oneOf"] {
+        if let Some(branches) = schema.get(combinator).and_then(|b| b.as_array()) {
+            types.extend(branches.iter().flat_map(|b| schema_types(b, root)));
+        }
+    }
+    types
+}
+
+/// The item schema of whichever branch of `schema` is a list.
+fn field_items<'a>(
+    schema: &'a serde_json::Value,
+    root: &'a serde_json::Value,
+) -> Option<&'a serde_json::Value> {
+    if let Some(reference) = schema.get("$ref").and_then(|r| r.as_str()) {
+        let found = root
+            .get("$defs")?
+            .get(reference.strip_prefix("#/$defs/")?)?;
+        return field_items(found, root);
+    }
+    if let Some(items) = schema.get("items") {
+        return Some(items);
+    }
+    ["anyOf", "oneOf"]
+        .iter()
+        .filter_map(|combinator| schema.get(*combinator)?.as_array())
+        .flatten()
+        .find_map(|branch| field_items(branch, root))
+}
+
+fn parse_override_value(value: &str) -> toml::Value {
+    if let Ok(flag) = value.parse::<bool>() {
+        return toml::Value::Boolean(flag);
+    }
+    let integer = match value.strip_prefix("0o") {
+        Some(octal) => i64::from_str_radix(octal, 8).ok(),
+        None => value.parse().ok(),
+    };
+    match integer {
+        Some(integer) => toml::Value::Integer(integer),
+        None => toml::Value::String(value.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn overridden(assignments: &[&str]) -> Result<toml::Table> {
+        let mut config = inline_config(&[
+            ("zshrc".into(), ".zshrc".into()),
+            ("nvim".into(), ".config/nvim".into()),
+        ]);
+        for assignment in assignments {
+            apply_override(&mut config, assignment)?;
+        }
+        Ok(config)
+    }
+
+    /// Each mapping's targets after `${name.target}` references are filled in.
+    fn resolved_targets(config: &str) -> Result<Vec<Vec<String>>> {
+        let mut config: Config = toml::from_str(config).unwrap();
+        config.resolve_target_refs()?;
+        Ok(config
+            .files
+            .iter()
+            .map(|mapping| mapping.targets().into_iter().map(str::to_string).collect())
+            .collect())
+    }
+
+    #[test]
+    fn target_references_expand() {
+        let targets = resolved_targets(
+            "[[files]]\nsource = \"plugins\"\ntarget = [\"${lua.target}/plugins\", \".vim\"]\n\n\
+             [[files]]\nname = \"nvim\"\nsource = \"nvim\"\ntarget = \".config/nvim/\"\n\n\
+             [[files]]\nname = \"lua\"\nsource = \"lua\"\ntarget = \"${nvim.target}/lua\"\n",
+        )
+        .unwrap();
+        assert_eq!(targets[2], [".config/nvim/lua"]);
+        // References resolve through other references, wherever they're defined
+        assert_eq!(targets[0], [".config/nvim/lua/plugins", ".vim"]);
+    }
+
+    #[test]
+    fn circular_target_references_are_rejected() {
+        let err = resolved_targets(
+            "[[files]]\nname = \"a\"\nsource = \"a\"\ntarget = \"${b.target}/a\"\n\n\
+             [[files]]\nname = \"b\"\nsource = \"b\"\ntarget = \"${a.target}/b\"\n",
+        )
+        .unwrap_err();
+        assert_eq!(err.to_string(), "Circular target reference: a -> b -> a");
+    }
+
+    #[test]
+    fn references_to_unknown_mappings_are_rejected() {
+        let err =
+            resolved_targets("[[files]]\nsource = \"lua\"\ntarget = \"${nvim.target}/lua\"\n")
+                .unwrap_err();
+        assert!(err.to_string().contains("unknown mapping nvim"), "{}", err);
+    }
+
+    #[test]
+    fn targets_are_normalized() {
+        assert_eq!(normalize_target(".config//nvim").unwrap(), ".config/nvim");
+        assert_eq!(
+            normalize_target("./.config/./nvim").unwrap(),
+            ".config/nvim"
+        );
+        assert_eq!(normalize_target("~/.zshrc").unwrap(), ".zshrc");
+        assert_eq!(normalize_target("/etc//hosts").unwrap(), "/etc/hosts");
+        // A trailing slash still means "inside this directory"
+        assert_eq!(
+            normalize_target("~/.config/nvim/").unwrap(),
+            ".config/nvim/"
+        );
+        assert_eq!(normalize_target(".config/nvim/.").unwrap(), ".config/nvim/");
+    }
+
+    #[test]
+    fn targets_leaving_home_or_naming_nothing_are_rejected() {
+        assert!(normalize_target("../.zshrc").is_err());
+        assert!(normalize_target(".config/../.zshrc").is_err());
+        assert!(normalize_target("~/").is_err());
+        assert!(normalize_target("").is_err());
+    }
+
+    #[test]
+    fn override_values_are_typed_by_how_they_look() {
+        assert_eq!(parse_override_value("true"), toml::Value::Boolean(true));
+        assert_eq!(parse_override_value("false"), toml::Value::Boolean(false));
+        assert_eq!(parse_override_value("42"), toml::Value::Integer(42));
+        assert_eq!(parse_override_value("0o644"), toml::Value::Integer(0o644));
+        assert_eq!(
+            parse_override_value("0o9"),
+            toml::Value::String("0o9".into())
+        );
+        assert_eq!(
+            parse_override_value("copy"),
+            toml::Value::String("copy".into())
+        );
+    }
+
+    #[test]
+    fn nested_overrides_follow_the_schema() {
+        let config = overridden(&[
+            "defaults.backup=false",
+            "defaults.os=linux, macos",
+            "secrets.command=123",
+            "vars.editor=true",
+        ])
+        .unwrap();
+        let defaults = config["defaults"].as_table().unwrap();
+        assert_eq!(defaults["backup"], toml::Value::Boolean(false));
+        assert_eq!(
+            defaults["os"],
+            toml::Value::Array(vec!["linux".into(), "macos".into()])
+        );
+        // Keys that only take a string keep the value as written
+        assert_eq!(
+            config["secrets"]["command"],
+            toml::Value::String("123".into())
+        );
+        assert_eq!(config["vars"]["editor"], toml::Value::String("true".into()));
+    }
+
+    #[test]
+    fn overrides_index_into_mappings() {
+        let config = overridden(&[
+            "files.1.strategy=copy",
+            "files.0.skip_if_exists=true",
+            "files.0.rename.dot_zshrc=.zshrc",
+        ])
+        .unwrap();
+        let files = config["files"].as_array().unwrap();
+        assert_eq!(files[1]["strategy"], toml::Value::String("copy".into()));
+        assert_eq!(files[0]["skip_if_exists"], toml::Value::Boolean(true));
+        assert_eq!(
+            files[0]["rename"]["dot_zshrc"],
+            toml::Value::String(".zshrc".into())
+        );
+        assert!(files[0].get("strategy").is_none());
+
+        let error = overridden(&["files.2.strategy=copy"]).unwrap_err();
+        assert!(error.to_string().contains("no entry 2"), "{}", error);
+    }
+
+    #[test]
+    fn unknown_or_table_override_keys_are_rejected() {
+        for key in ["defaults.strategyy", "files.0.targt", "nope", "vars."] {
+            let error = overridden(&[&format!("{}=x", key)]).unwrap_err();
+            assert!(
+                error.to_string().contains("Unknown config key"),
+                "{}",
+                error
+            );
+        }
+        for key in ["files", "files.0", "defaults"] {
+            let error = overridden(&[&format!("{}=x", key)]).unwrap_err();
+            assert!(error.to_string().contains("is a table"), "{}", error);
+        }
+    }
+}
@@ -0,0 +1,298 @@
+use colored::*;
+
+/// How `diff` renders the difference between a target and what install
+/// would put there.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum DiffFormat {
+    /// Changed lines with three lines of context, like `diff -u`.
+    #[default]
+    Unified,
+    /// The current target and the new content in two columns.
+    SideBySide,
+    /// Only the mappings that differ, one per line.
+    NameOnly,
+}
+
+/// Lines of unchanged context kept around each change in unified output.
+const CONTEXT: usize = 3;
+
+/// Above this many line pairs the diff isn't worth computing and the files
+/// are only reported as different.
+const MAX_CELLS: usize = 4_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op<'a> {
+    Equal(&'a str),
+    Delete(&'a str),
+    Insert(&'a str),
+}
+
+/// The shortest edit turning `old` into `new`, line by line, from their
+/// longest common subsequence. `None` if the files are too large.
+fn diff_lines<'a>(old: &[&'a str], new: &[&'a str]) -> Option<Vec<Op<'a>>> {
+    if old.len().saturating_mul(new.len()) > MAX_CELLS {
+        return None;
+    }
+    // lcs[i][j] is the common subsequence length of old[i..] and new[j..]
+    let width = new.len() + 1;
+    let mut lcs = vec![0u32; (old.len() + 1) * width];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i * width + j] = if old[i] == new[j] {
+                lcs[(i + 1) * width + j + 1] + 1
+            } else {
+                lcs[(i + 1) * width + j].max(lcs[i * width + j + 1])
+            };
+        }
+    }
+
+    let (mut i, mut j) = (0, 0);
+    let mut ops = Vec::new();
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            ops.push(Op::Equal(old[i]));
+            i += 1;
+            j += 1;
+        } else if i < old.len()
+            && (j == new.len() || lcs[(i + 1) * width + j] >= lcs[i * width + j + 1])
+        {
+            // Deletions go first, as in `diff -u`
+            ops.push(Op::Delete(old[i]));
+            i += 1;
+        } else {
+            ops.push(Op::Insert(new[j]));
+            j += 1;
+        }
+    }
+    Some(ops)
+}
+
+/// Renders the change from `old` to `new` as a unified diff between the two
+/// labels. Empty when they're equal.
+pub fn unified(old: &str, new: &str, old_label: &str, new_label: &str) -> String {
+    let old_lines = split_lines(old);
+    let new_lines = split_lines(new);
+    let Some(ops) = diff_lines(&old_lines, &new_lines) else {
+        return format!("Files {} and {} differ\n", old_label, new_label);
+    };
+    if ops.iter().all(|op| matches!(op, Op::Equal(_))) {
+        return String::new();
+    }
+
+    let mut out = format!(
+        "{}\n{}\n",
+        format!("--- {}", old_label).bold(),
+        format!("+++ {}", new_label).bold()
+    );
+    // Indices of changed ops, grouped into hunks whose context overlaps
+    let changed: Vec<usize> = (0..ops.len())
+        .filter(|&index| !matches!(ops[index], Op::Equal(_)))
+        .collect();
+    let mut hunks: Vec<(usize, usize)> = Vec::new();
+    for &index in &changed {
+        let start = index.saturating_sub(CONTEXT);
+        let end = (index + CONTEXT + 1).min(ops.len());
+        match hunks.last_mut() {
+            Some(last) if start <= last.1 => last.1 = end,
+            _ => hunks.push((start, end)),
+        }
+    }
+
+    for (start, end) in hunks {
+        // Line numbers where the hunk starts on each side
+        let before = &ops[..start];
+        let old_start = before
+            .iter()
+            .filter(|op| !matches!(op, Op::Insert(_)))
+            .count();
+        let new_start = before
+            .iter()
+            .filter(|op| !matches!(op, Op::Delete(_)))
+            .count();
+        let hunk = &ops[start..end];
+        let old_count = hunk
+            .iter()
+            .filter(|op| !matches!(op, Op::Insert(_)))
+            .count();
+        let new_count = hunk
+            .iter()
+            .filter(|op| !matches!(op, Op::Delete(_)))
+            .count();
+        out.push_str(&format!(
+            "{}\n",
+            format!(
+                "@@ -{},{} +{},{} @@",
+                line_number(old_start, old_count),
+                old_count,
+                line_number(new_start, new_count),
+                new_count
+            )
+            .cyan()
+        ));
+        for op in hunk {
+            let (Op::Equal(text) | Op::Delete(text) | Op::Insert(text)) = *op;
+            let line = match op {
+                Op::Equal(_) => format!(" {}", strip_newline(text)).normal(),
+                Op::Delete(_) => format!("-{}", strip_newline(text)).red(),
+                Op::Insert(_) => format!("+{}", strip_newline(text)).green(),
+            };
+            out.push_str(&format!("{}\n", line));
+            if !text.ends_with('\n') {
+                out.push_str("\\ No newline at end of file\n");
+            }
+        }
+    }
+    out
+}
+
+/// The lines of `text`, each with its line ending, so a missing newline at
+/// the end counts as a change.
+fn split_lines(text: &str) -> Vec<&str> {
+    text.split_inclusive('\n').collect()
+}
+
+fn strip_newline(line: &str) -> &str {
+    line.strip_suffix('\n')
+        .map(|line| line.strip_suffix('\r').unwrap_or(line))
+        .unwrap_or(line)
+}
+
+/// The 1-based line a hunk starts at, or the line before it for an empty
+/// side, as `diff -u` numbers them.
+fn line_number(start: usize, count: usize) -> usize {
+    if count == 0 {
+        start
+    } else {
+        start + 1
+    }
+}
+
+/// Renders `old` and `new` in two columns of `width` characters each,
+/// marking changed lines between them. Empty when they're equal.
+pub fn side_by_side(old: &str, new: &str, width: usize) -> String {
+    let old_lines = split_lines(old);
+    let new_lines = split_lines(new);
+    let Some(ops) = diff_lines(&old_lines, &new_lines) else {
+        return "Files differ\n".to_string();
+    };
+    if ops.iter().all(|op| matches!(op, Op::Equal(_))) {
+        return String::new();
+    }
+
+    let column = |line: &str| {
+        let line: String = strip_newline(line).chars().take(width).collect();
+        format!("{:width$}", line, width = width)
+    };
+    let mut out = String::new();
+    let mut index = 0;
+    while index < ops.len() {
+        match ops[index] {
+            Op::Equal(line) => {
+                out.push_str(&format!("{}   {}\n", column(line), strip_newline(line)));
+                index += 1;
+            }
+            _ => {
+                // Pair up a run of deletions with the insertions after it
+                let deleted: Vec<&str> = ops[index..]
+                    .iter()
+                    .map_while(|op| match op {
+                        Op::Delete(line) => Some(*line),
+                        _ => None,
+                    })
+                    .collect();
+                index += deleted.len();
+                let inserted: Vec<&str> = ops[index..]
+                    .iter()
+                    .map_while(|op| match op {
+                        Op::Insert(line) => Some(*line),
+                        _ => None,
+                    })
+                    .collect();
+                index += inserted.len();
+                for row in 0..deleted.len().max(inserted.len()) {
+                    let (left, right) = (deleted.get(row), inserted.get(row));
+                    let marker = match (left, right) {
+                        (Some(_), Some(_)) => "|".yellow(),
+                        (Some(_), None) => "<".red(),
+                        _ => ">".green(),
+                    };
+                    out.push_str(&format!(
+                        "{} {} {}\n",
+                        column(left.copied().unwrap_or_default()).red(),
+                        marker,
+                        strip_newline(right.copied().unwrap_or_default()).green()
+                    ));
+                }
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn numbered(lines: std::ops::RangeInclusive<usize>) -> String {
+        lines.map(|n| format!("{}\n", n)).collect()
+    }
+
+    /// `1` to `20`, one per line, with the given lines replaced.
+    fn edited(changes: &[(usize, &str)]) -> String {
+        (1..=20)
+            .map(|n| match changes.iter().find(|(line, _)| *line == n) {
+                Some((_, text)) => format!("{}\n", text),
+                None => format!("{}\n", n),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn hunks_keep_three_lines_of_context() {
+        colored::control::set_override(false);
+        let old = numbered(1..=20);
+        let new = edited(&[(5, "five"), (17, "seventeen")]);
+        let diff = unified(&old, &new, "a", "b");
+        assert_eq!(
+            diff,
+            "--- a\n+++ b\n\
+             @@ -2,7 +2,7 @@\n 2\n 3\n 4\n-5\n+five\n 6\n 7\n 8\n\
+             @@ -14,7 +14,7 @@\n 14\n 15\n 16\n-17\n+seventeen\n 18\n 19\n 20\n"
+        );
+
+        // Changes whose context overlaps share one hunk
+        let new = edited(&[(5, "five"), (9, "nine")]);
+        assert_eq!(unified(&old, &new, "a", "b").matches("@@ -").count(), 1);
+    }
+
+    #[test]
+    fn empty_files_diff_from_line_zero() {
+        colored::control::set_override(false);
+        assert_eq!(unified("", "", "a", "b"), "");
+        assert_eq!(
+            unified("", "set -o vi\n", "a", "b"),
+            "--- a\n+++ b\n@@ -0,0 +1,1 @@\n+set -o vi\n"
+        );
+        assert_eq!(
+            unified("set -o vi\n", "", "a", "b"),
+            "--- a\n+++ b\n@@ -1,1 +0,0 @@\n-set -o vi\n"
+        );
+    }
+
+    #[test]
+    fn missing_final_newline_is_a_change() {
+        colored::control::set_override(false);
+        assert_eq!(
+            unified("a\nb", "a\nb\n", "a", "b"),
+            "--- a\n+++ b\n@@ -1,2 +1,2 @@\n a\n-b\n\\ No newline at end of file\n+b\n"
+        );
+    }
+
+    #[test]
+    fn huge_files_are_only_reported_as_different() {
+        let old = numbered(1..=2001);
+        let new = numbered(2..=2002);
+        assert_eq!(unified(&old, &new, "a", "b"), "Files a and b differ\n");
+        assert_eq!(side_by_side(&old, &new, 40), "Files differ\n");
+    }
+}
@@ -1,226 +1,3178 @@
+mod backup;
+mod deep;
+mod diff;
+mod explain;
+mod fix;
+mod graph;
+mod hooks;
+mod self_test;
+mod sync;
+#[cfg(test)]
+mod tests;
+mod tree;
+mod watch;
+
+pub use explain::{Check, ExplainedTarget, Explanation};
+
+use crate::config::{self, Config, ConfigFormat, FileMapping, Strategy, Targets};
+use crate::events::{Emitter, Event};
+use crate::filter::{glob_match, DirFilter};
+use crate::fsutil::{
+    copy_recursive, create_dir_all_with_mode, loops_back, replace_link_with_copy,
+    replace_with_symlink, symlink, symlink_cycle, sync_dir, write_atomic, write_atomic_with_mode,
+};
+use crate::report::{InstallReport, MappingReport, ReportFormat};
+use crate::state::{BackupRecord, Manifest, State, TopicMeta};
+use crate::status::{
+    classify, classify_copy, classify_fast, links_to, StatusSummary, TargetStatus,
+};
+use crate::template;
+use crate::transaction::Transaction;
 use anyhow::{Context, Result};
 use colored::*;
-use serde::Deserialize;
+use serde::Serialize;
 use std::{
+    cell::{Cell, OnceCell, RefCell},
+    collections::{BTreeMap, HashMap, HashSet},
+    env,
+    ffi::OsStr,
     fs,
-    path::{Path, PathBuf},
+    path::{Component, Path, PathBuf},
+    process::{Command, Stdio},
+    time::Instant,
 };
 
 pub struct DotfileManager {
     dotfiles_dir: PathBuf,
+    backup_root: PathBuf,
     backup_dir: PathBuf,
     home_dir: PathBuf,
-    verbose: bool,
+    options: Options,
+    emitter: Emitter,
+    config: OnceCell<Config>,
+    state: RefCell<State>,
+    manifest: RefCell<Manifest>,
+    /// Undo journal for the running `--transactional` install.
+    transaction: RefCell<Option<Transaction>>,
+    /// Results of `when` predicates already run, by command.
+    predicates: RefCell<HashMap<String, bool>>,
+    /// (uid, gid) to give created files when installing for `--user`.
+    owner: Option<(u32, u32)>,
+    /// Where each mapping source was found among the source roots.
+    sources: RefCell<HashMap<String, PathBuf>>,
+    /// Set while `--interactive` previews a mapping, which runs it as a dry
+    /// run.
+    planning: Cell<bool>,
+    /// Shell commands undoing each change so far, for `--reverse-script`.
+    reverse: RefCell<Vec<String>>,
+}
+
+/// What to do with a mapping `install --interactive` asked about.
+enum Confirmation {
+    Apply,
+    Skip,
+    SkipRemaining,
+}
+
+/// What a single file operation did.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkAction {
+    Linked,
+    BackedUpAndLinked,
+    Skipped,
+}
+
+/// Every file touched while installing one mapping, grouped by what happened to it.
+#[derive(Serialize, Debug, Default)]
+pub struct InstallOutcome {
+    pub linked: Vec<PathBuf>,
+    pub backed_up: Vec<PathBuf>,
+    pub skipped: Vec<PathBuf>,
+}
+
+impl InstallOutcome {
+    fn record(&mut self, dest: PathBuf, action: LinkAction) {
+        match action {
+            LinkAction::Linked => self.linked.push(dest),
+            LinkAction::BackedUpAndLinked => {
+                self.backed_up.push(dest.clone());
+                self.linked.push(dest);
+            }
+            LinkAction::Skipped => self.skipped.push(dest),
+        }
+    }
+
+    fn merge(&mut self, other: InstallOutcome) {
+        self.linked.extend(other.linked);
+        self.backed_up.extend(other.backed_up);
+        self.skipped.extend(other.skipped);
+    }
+}
+
+/// How existing targets are saved before being replaced.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum BackupStrategy {
+    /// Rename the target into the backup directory (fast).
+    #[default]
+    Move,
+    /// Copy the target and only remove it once the link is in place (safer).
+    Copy,
+}
+
+/// Where in a backup directory each saved target goes.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum BackupLayout {
+    /// At its path relative to home, so `~/.config/nvim/init.lua` is saved
+    /// as `.config/nvim/init.lua`. Targets outside home keep their absolute
+    /// path minus the leading `/`.
+    #[default]
+    Mirror,
+    /// Directly in the backup directory under its file name. Targets that
+    /// share a name overwrite each other.
+    Flat,
+}
+
+/// Per-mapping settings that apply to every file a mapping installs.
+#[derive(Debug, Clone)]
+struct MappingSettings {
+    /// Whether existing targets are backed up before being replaced.
+    backup: bool,
+    strategy: Strategy,
+    /// Permissions for copied and rendered files.
+    mode: Option<u32>,
+    /// Drop write permission from copied and rendered files.
+    readonly: bool,
+    /// Subdirectory levels walked before directories are placed whole.
+    max_depth: Option<usize>,
+    /// File name in the source -> name to give its target.
+    rename: BTreeMap<String, String>,
+    /// Leave targets that already exist alone.
+    skip_if_exists: bool,
 }
 
-#[derive(Deserialize, Debug)]
-struct FileMapping {
-    source: String,
-    target: String,
+impl MappingSettings {
+    /// Where a file named `name` in a directory source lands in `target_dir`.
+    fn file_target(&self, target_dir: &Path, name: &std::ffi::OsStr) -> PathBuf {
+        match self.rename.get(name.to_string_lossy().as_ref()) {
+            Some(renamed) => target_dir.join(renamed),
+            None => target_dir.join(name),
+        }
+    }
 }
 
-#[derive(Deserialize, Debug)]
-struct Config {
-    files: Vec<FileMapping>,
+/// Settings that come from the command line rather than the config file.
+#[derive(Debug, Default, Clone)]
+pub struct Options {
+    pub verbose: bool,
+    /// Explicit config file, searched before any of the standard locations.
+    pub config: Option<PathBuf>,
+    /// Emit install events as JSON lines instead of human-readable output.
+    pub events: bool,
+    /// Machine name used in place of the real hostname.
+    pub machine: Option<String>,
+    /// Fail instead of creating missing target directories.
+    pub no_create_dirs: bool,
+    /// Report how long each mapping took to install.
+    pub time: bool,
+    /// Keep `dotfiles_dir` as given instead of resolving symlinks in it.
+    pub no_canonicalize: bool,
+    pub backup_strategy: BackupStrategy,
+    pub backup_layout: BackupLayout,
+    /// Report what install would do without touching the filesystem.
+    pub dry_run: bool,
+    /// Home directory to install into instead of the real one.
+    pub home: Option<PathBuf>,
+    /// Permissions for newly created backup directories. Defaults to 0700
+    /// since backups can hold private configs.
+    pub backup_mode: Option<u32>,
+    /// Dotfiles repo location instead of `~/dotfiles`.
+    pub dotfiles_dir: Option<PathBuf>,
+    /// Replace existing targets without backing them up.
+    pub no_backup: bool,
+    /// Where install appends its audit log, instead of
+    /// `~/.dotfiles_backup/audit.log`.
+    pub audit_log: Option<PathBuf>,
+    /// Fail install when a required secret is missing instead of warning.
+    pub strict_secrets: bool,
+    /// Copy current targets into a backup without linking anything.
+    pub backup_only: bool,
+    /// Only install mappings with missing or broken targets.
+    pub only_missing: bool,
+    /// Syntax of the config file, overriding detection from its extension.
+    pub config_format: Option<ConfigFormat>,
+    /// `key.path=value` assignments applied over the loaded config.
+    pub overrides: Vec<String>,
+    /// Let targets resolve outside the home directory.
+    pub allow_outside_home: bool,
+    /// Write a summary of the install run here.
+    pub report: Option<PathBuf>,
+    pub report_format: ReportFormat,
+    /// Hide each mapping's install output unless that mapping fails.
+    pub output_on_error: bool,
+    /// Subdirectory levels walked in directory sources, for mappings that
+    /// don't set `max_depth` themselves. Unlimited when unset.
+    pub max_depth: Option<usize>,
+    /// Undo every change install made if any mapping fails.
+    pub transactional: bool,
+    /// Check that each link install created resolves to its source.
+    pub verify_links: bool,
+    /// Install into this user's home instead of our own.
+    pub user: Option<String>,
+    /// Don't look for missing secrets.
+    pub no_secrets_check: bool,
+    /// Print only this run's backup directory, keeping the rest of install
+    /// quiet.
+    pub print_backup_dir: bool,
+    /// Show each mapping's planned changes and ask before applying them.
+    pub interactive: bool,
+    /// Sources of directory mappings to copy file by file instead of linking,
+    /// as if they set `strategy = "copy"`.
+    pub copy_dirs: Vec<String>,
+    /// `(source, target)` pairs used as the whole config instead of a file.
+    pub mappings: Vec<(String, String)>,
+    /// Fail on config keys nothing reads instead of warning about them.
+    pub strict_config: bool,
+    /// Topic -> directory under home, consulted by [`DotfileManager::process_topic`]
+    /// and [`DotfileManager::get_target_path`] before their built-in defaults.
+    pub topic_targets: HashMap<String, String>,
+    /// Warn after install when backups take up more than this many bytes.
+    pub backup_warn_size: Option<u64>,
+    /// Git ref whose tree is installed instead of the working tree.
+    pub config_from_git: Option<String>,
+    /// Where install writes a shell script undoing what it changed.
+    pub reverse_script: Option<PathBuf>,
+    /// Operating system mappings are selected for, instead of this one.
+    pub os: Option<String>,
+    /// Skip mappings limited to these operating systems.
+    pub exclude_os: Vec<String>,
+    /// Show full paths in list and status output instead of `~/`- and
+    /// repo-relative ones.
+    pub absolute: bool,
 }
 
 impl DotfileManager {
-    pub fn new(verbose: bool) -> Result<Self> {
-        let home = dirs::home_dir().context("Could not determine home directory")?;
-        let dotfiles = home.join("dotfiles");
-        let backup = home
-            .join(".dotfiles_backup")
-            .join(chrono::Local::now().format("%Y%m%d_%H%M%S").to_string());
+    pub fn new(options: Options) -> Result<Self> {
+        let account = options
+            .user
+            .as_deref()
+            .map(UserAccount::lookup)
+            .transpose()?;
+        let (home, home_source) = match (&options.home, &account) {
+            (Some(home), _) => (home.clone(), "--home".to_string()),
+            (None, Some(account)) => (account.home.clone(), format!("user {}", account.name)),
+            (None, None) => match home_from_env() {
+                Some((var, home)) => (home, format!("${}", var)),
+                None => (
+                    dirs::home_dir().context("Could not determine home directory")?,
+                    "the platform default".to_string(),
+                ),
+            },
+        };
+        let mut dotfiles = options
+            .dotfiles_dir
+            .clone()
+            .unwrap_or_else(|| home.join("dotfiles"));
+        // Link to the real repo location so removing a symlinked ~/dotfiles
+        // doesn't break every installed link
+        if !options.no_canonicalize {
+            if let Ok(canonical) = fs::canonicalize(&dotfiles) {
+                dotfiles = canonical;
+            }
+        }
+        let exported = match &options.config_from_git {
+            Some(git_ref) => Some(sync::export_ref(&dotfiles, git_ref)?),
+            None => None,
+        };
+        let mut manager = Self::build(
+            exported.clone().unwrap_or(dotfiles),
+            home,
+            options,
+            OnceCell::new(),
+        )?;
+        if let Some(exported) = exported {
+            manager.emitter.message(format!(
+                "{} {} (links point into it)",
+                "Installing from".blue(),
+                exported.display()
+            ));
+        }
+        manager.log(&format!(
+            "Using home directory {} (from {})",
+            manager.home_dir.display(),
+            home_source
+        ));
+        if let Some(account) = account {
+            if is_root() {
+                manager.owner = Some((account.uid, account.gid));
+            } else {
+                manager.emitter.emit(&Event::Warning {
+                    message: &format!(
+                        "Not running as root: files installed for {} will be owned by you",
+                        account.name
+                    ),
+                });
+            }
+        }
+        Ok(manager)
+    }
+
+    /// Builds a manager around an already-parsed config and explicit
+    /// directories, for embedders and configs assembled in memory. The config
+    /// file is never looked up; `options.home` and `options.dotfiles_dir` are
+    /// ignored in favour of the given paths.
+    pub fn with_config(
+        mut config: Config,
+        dotfiles_dir: PathBuf,
+        home_dir: PathBuf,
+        options: Options,
+    ) -> Result<Self> {
+        config.check_schema_version()?;
+        config.flatten_topics();
+        config.resolve_target_refs()?;
+        config.normalize_targets()?;
+        config.apply_defaults();
+        config.copy_sources(&options.copy_dirs)?;
+        Self::build(dotfiles_dir, home_dir, options, OnceCell::from(config))
+    }
+
+    fn build(
+        dotfiles_dir: PathBuf,
+        home_dir: PathBuf,
+        options: Options,
+        config: OnceCell<Config>,
+    ) -> Result<Self> {
+        let backup_root = home_dir.join(".dotfiles_backup");
+        let backup = backup_root.join(chrono::Local::now().format("%Y%m%d_%H%M%S").to_string());
+        let state = State::load(&backup_root.join("state.toml"))?;
 
         Ok(Self {
-            dotfiles_dir: dotfiles,
+            dotfiles_dir,
+            backup_root,
             backup_dir: backup,
-            home_dir: home,
-            verbose,
+            home_dir,
+            emitter: if options.print_backup_dir {
+                Emitter::quiet()
+            } else {
+                Emitter::new(options.events)
+            },
+            options,
+            config,
+            state: RefCell::new(state),
+            manifest: RefCell::new(Manifest::default()),
+            transaction: RefCell::new(None),
+            predicates: RefCell::new(HashMap::new()),
+            owner: None,
+            sources: RefCell::new(HashMap::new()),
+            planning: Cell::new(false),
+            reverse: RefCell::new(Vec::new()),
         })
     }
 
+    /// Takes the exclusive lock that keeps mutating commands from running at
+    /// the same time. The lock is held until the returned file is dropped.
+    pub fn lock(&self) -> Result<fs::File> {
+        create_dir_all_with_mode(&self.backup_root, self.options.backup_mode.unwrap_or(0o700))
+            .context("Failed to create backup directory")?;
+        let path = self.backup_root.join(".lock");
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&path)
+            .with_context(|| format!("Failed to open lock file {}", path.display()))?;
+        self.chown(&self.backup_root)?;
+        self.chown(&path)?;
+        match file.try_lock() {
+            Ok(()) => Ok(file),
+            Err(fs::TryLockError::WouldBlock) => anyhow::bail!(
+                "Another dotfiles operation is in progress (lock held on {})",
+                path.display()
+            ),
+            Err(fs::TryLockError::Error(err)) => {
+                Err(err).with_context(|| format!("Failed to lock {}", path.display()))
+            }
+        }
+    }
+
+    fn state_path(&self) -> PathBuf {
+        self.backup_root.join("state.toml")
+    }
+
     pub fn load_config(&self) -> Result<Config> {
-        let config_path = self.dotfiles_dir.join("config.toml");
-        let config_str = fs::read_to_string(config_path).context("Failed to read config.toml")?;
-        let config: Config = toml::from_str(&config_str).context("Failed to parse config.toml")?;
+        let mut table = if self.options.mappings.is_empty() {
+            let config_path = self.resolve_config_path()?;
+            let config_str =
+                fs::read_to_string(&config_path).context("Failed to read config.toml")?;
+            self.config_format(&config_path)
+                .parse(&config_str)
+                .with_context(|| format!("Failed to parse {}", config_path.display()))?
+        } else {
+            self.log("Using --mapping flags instead of a config file");
+            config::inline_config(&self.options.mappings)
+        };
+        for assignment in &self.options.overrides {
+            config::apply_override(&mut table, assignment)?;
+        }
+        let unknown = config::unknown_keys(&table);
+        if self.options.strict_config && !unknown.is_empty() {
+            anyhow::bail!("Invalid config:\n  {}", unknown.join("\n  "));
+        }
+        for message in &unknown {
+            self.emitter.emit(&Event::Warning {
+                message: &format!("Ignoring {} (--strict-config rejects it)", message),
+            });
+        }
+        let mut config: Config = toml::Value::Table(table)
+            .try_into()
+            .context("Failed to parse config.toml")?;
+        config.check_schema_version()?;
+        config.flatten_topics();
+        config.resolve_target_refs()?;
+        for (original, normalized) in config.normalize_targets()? {
+            self.log(&format!("Normalized target {} to {}", original, normalized));
+        }
+        config.apply_defaults();
+        config.copy_sources(&self.options.copy_dirs)?;
         Ok(config)
     }
 
-    pub fn log(&self, msg: &str) {
-        if self.verbose {
-            println!("{} {}", "INFO:".blue(), msg);
+    /// How to parse the config at `path`: `--format-in`, then
+    /// `$DOTFILES_CONFIG_FORMAT`, then the file extension, then TOML. An
+    /// explicit format that contradicts the extension wins, with a warning.
+    pub fn config_format(&self, path: &Path) -> ConfigFormat {
+        let from_extension = ConfigFormat::from_extension(path);
+        let explicit = self.options.config_format.or_else(|| {
+            let name = env::var("DOTFILES_CONFIG_FORMAT").ok()?;
+            let parsed = <ConfigFormat as clap::ValueEnum>::from_str(&name, true).ok();
+            if parsed.is_none() {
+                self.emitter.emit(&Event::Warning {
+                    message: &format!("Ignoring unknown DOTFILES_CONFIG_FORMAT {}", name),
+                });
+            }
+            parsed
+        });
+
+        match (explicit, from_extension) {
+            (Some(explicit), Some(extension)) if explicit != extension => {
+                self.emitter.emit(&Event::Warning {
+                    message: &format!(
+                        "Parsing {} as {:?} although its extension suggests {:?}",
+                        path.display(),
+                        explicit,
+                        extension
+                    ),
+                });
+                explicit
+            }
+            (Some(format), _) | (None, Some(format)) => format,
+            (None, None) => ConfigFormat::Toml,
         }
     }
 
-    pub fn install(&self) -> Result<()> {
-        println!("{}", "Installing dotfiles...".green());
+    /// The config file, for commands that edit it in place. Only TOML can be
+    /// edited without losing comments and layout.
+    fn editable_config_path(&self) -> Result<PathBuf> {
+        let path = self.resolve_config_path()?;
+        if self.config_format(&path) != ConfigFormat::Toml {
+            anyhow::bail!(
+                "Editing {} is only supported for TOML configs",
+                path.display()
+            );
+        }
+        Ok(path)
+    }
 
-        // Create backup directory
-        fs::create_dir_all(&self.backup_dir).context("Failed to create backup directory")?;
+    /// The config file parsed for editing, along with its path. Write it back
+    /// with [`write_config`].
+    fn editable_config(&self) -> Result<(PathBuf, toml_edit::DocumentMut)> {
+        let path = self.editable_config_path()?;
+        let contents = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let doc = contents
+            .parse()
+            .with_context(|| format!("Failed to parse {}", path.display()))?;
+        Ok((path, doc))
+    }
 
-        // Load and process config
+    /// The config after every load step, with templated targets expanded
+    /// and mappings that don't apply on this run marked `enabled = false`.
+    pub fn effective_config(&self) -> Result<Config> {
+        let mut config = self.load_config()?;
+        for index in 0..config.files.len() {
+            let mapping = &config.files[index];
+            let enabled = self.is_enabled(mapping);
+            let target = match &mapping.target {
+                Targets::One(target) => Targets::One(self.expand_target(target)?),
+                Targets::Many(targets) => Targets::Many(
+                    targets
+                        .iter()
+                        .map(|target| self.expand_target(target))
+                        .collect::<Result<_>>()?,
+                ),
+            };
+            let mapping = &mut config.files[index];
+            mapping.target = target;
+            if !enabled {
+                mapping.enabled = Some(false);
+            }
+        }
+        Ok(config)
+    }
+
+    /// The config this manager acts on, read from disk on first use.
+    pub fn config(&self) -> Result<&Config> {
+        if let Some(config) = self.config.get() {
+            return Ok(config);
+        }
         let config = self.load_config()?;
+        Ok(self.config.get_or_init(|| config))
+    }
 
-        for mapping in config.files {
-            let source = self.dotfiles_dir.join(&mapping.source);
-            let target = self.home_dir.join(&mapping.target);
+    /// Finds the config file in order: `--config`, `$DOTFILES_CONFIG`,
+    /// `dotfiles_dir/config.toml`, `$XDG_CONFIG_HOME/dotfiles/config.toml`,
+    /// then `~/.dotfiles.toml`.
+    pub fn resolve_config_path(&self) -> Result<PathBuf> {
+        let candidates = config::candidate_paths(
+            self.options.config.as_deref(),
+            &self.dotfiles_dir,
+            &self.home_dir,
+        );
 
-            if source.is_dir() {
-                fs::create_dir_all(&target)?;
-                self.process_directory(&source, &target)?;
-            } else {
-                self.link_file(&source, &target.parent().unwrap_or(&self.home_dir))?;
+        match candidates.iter().find(|path| path.is_file()) {
+            Some(path) => {
+                self.log(&format!("Using config: {}", path.display()));
+                Ok(path.clone())
+            }
+            None => {
+                let searched: Vec<String> = candidates
+                    .iter()
+                    .map(|path| format!("  {}", path.display()))
+                    .collect();
+                anyhow::bail!("No config file found. Searched:\n{}", searched.join("\n"))
             }
         }
+    }
 
-        self.check_secrets()?;
-        Ok(())
+    /// Whether a mapping applies on this run, honouring `--os`,
+    /// `--exclude-os` and the mapping's `when` predicate.
+    pub fn is_enabled(&self, mapping: &FileMapping) -> bool {
+        let os = self.options.os.as_deref().unwrap_or(env::consts::OS);
+        mapping.is_enabled_on(os, &self.options.exclude_os)
+            && mapping
+                .when
+                .as_deref()
+                .is_none_or(|when| self.predicate(when))
     }
 
-    pub fn process_topic(&self, topic: &str, topic_path: &Path) -> Result<()> {
-        println!("{} {}", "Processing topic:".green(), topic);
+    /// Why [`DotfileManager::is_enabled`] excludes a mapping, for display.
+    pub fn skip_reason(&self, mapping: &FileMapping) -> Option<String> {
+        if self.is_enabled(mapping) {
+            return None;
+        }
+        let os = self.options.os.as_deref().unwrap_or(env::consts::OS);
+        Some(match &mapping.when {
+            Some(when) if mapping.is_enabled_on(os, &self.options.exclude_os) => {
+                format!("skipped (`{}` failed)", when)
+            }
+            _ => "disabled".to_string(),
+        })
+    }
 
-        // Determine target directory based on topic
-        let target_dir = match topic {
-            "zellij" => self.home_dir.join(".config").join("zellij"),
-            "nvim" => self.home_dir.join(".config").join("nvim"),
-            _ => self.home_dir.clone(),
-        };
+    /// A short token for why install skips a mapping: `disabled`, `os` or
+    /// `when`.
+    fn skip_token(&self, mapping: &FileMapping) -> Option<&'static str> {
+        if self.is_enabled(mapping) {
+            None
+        } else if !mapping.enabled.unwrap_or(true) {
+            Some("disabled")
+        } else if !mapping.is_enabled_on(
+            self.options.os.as_deref().unwrap_or(env::consts::OS),
+            &self.options.exclude_os,
+        ) {
+            Some("os")
+        } else {
+            Some("when")
+        }
+    }
 
-        // Create target directory if it doesn't exist
-        fs::create_dir_all(&target_dir)?;
+    /// Counts a mapping install left alone, naming it under `--verbose`.
+    fn note_skip(
+        &self,
+        skipped: &mut BTreeMap<&'static str, usize>,
+        mapping: &FileMapping,
+        token: &'static str,
+    ) {
+        self.log(&format!("Skipped {} ({})", mapping.source, token));
+        *skipped.entry(token).or_default() += 1;
+    }
 
-        // Process all files in the topic directory
-        self.process_directory(topic_path, &target_dir)?;
+    /// Runs a `when` predicate once per run and remembers whether it exited 0.
+    fn predicate(&self, command: &str) -> bool {
+        if let Some(&passed) = self.predicates.borrow().get(command) {
+            return passed;
+        }
+        if self.options.dry_run {
+            self.emitter.emit(&Event::Warning {
+                message: &format!(
+                    "Running `when` predicate `{}` despite --dry-run; it must not change anything",
+                    command
+                ),
+            });
+        }
+        self.log(&format!("Evaluating predicate: {}", command));
+        let passed = shell_command(command)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .is_ok_and(|status| status.success());
+        self.predicates
+            .borrow_mut()
+            .insert(command.to_string(), passed);
+        passed
+    }
 
+    /// Hands a file or link install created to the `--user` being installed
+    /// for. Symlinks themselves are changed, not what they point to.
+    fn chown(&self, path: &Path) -> Result<()> {
+        let Some((uid, gid)) = self.owner else {
+            return Ok(());
+        };
+        #[cfg(unix)]
+        std::os::unix::fs::lchown(path, Some(uid), Some(gid))
+            .with_context(|| format!("Failed to change owner of {}", path.display()))?;
+        #[cfg(not(unix))]
+        let _ = (path, uid, gid);
         Ok(())
     }
 
-    pub fn process_directory(&self, src_dir: &Path, target_dir: &Path) -> Result<()> {
-        for entry in fs::read_dir(src_dir)? {
-            let entry = entry?;
-            let path = entry.path();
+    /// `a -> b -> a` for the links of a cycle.
+    fn describe_cycle(&self, cycle: &[PathBuf]) -> String {
+        let mut members: Vec<String> = cycle.iter().map(|link| self.display_target(link)).collect();
+        members.extend(members.first().cloned());
+        members.join(" -> ")
+    }
 
-            if path.is_file() {
-                self.link_file(&path, target_dir)?;
-            } else if path.is_dir() {
-                let new_target = target_dir.join(path.file_name().unwrap());
-                fs::create_dir_all(&new_target)?;
-                self.process_directory(&path, &new_target)?;
-            }
+    /// A target for display: `~/`-relative unless `--absolute` was given.
+    pub fn display_target(&self, path: &Path) -> String {
+        match path.strip_prefix(&self.home_dir) {
+            Ok(relative) if !self.options.absolute => format!("~/{}", relative.display()),
+            _ => path.display().to_string(),
         }
+    }
 
-        Ok(())
+    /// A source for display: relative to the dotfiles repo unless
+    /// `--absolute` was given.
+    pub fn display_source(&self, path: &Path) -> String {
+        match path.strip_prefix(&self.dotfiles_dir) {
+            Ok(relative) if !self.options.absolute => relative.display().to_string(),
+            _ => path.display().to_string(),
+        }
     }
 
-    pub fn link_file(&self, src: &Path, target_dir: &Path) -> Result<()> {
-        let file_name = src.file_name().context("Invalid file name")?;
-        let dest = target_dir.join(file_name);
+    pub fn log(&self, msg: &str) {
+        if self.options.verbose && self.emitter.prints_text() {
+            println!("{} {}", "INFO:".blue(), msg);
+        }
+    }
 
-        self.log(&format!("Processing file: {:?}", file_name));
+    /// Installs every mapping, or only those belonging to `topic` when given.
+    pub fn install(&self, topic: Option<&str>) -> Result<()> {
+        if self.options.print_backup_dir {
+            println!("{}", self.backup_dir.display());
+            if self.options.dry_run {
+                return Ok(());
+            }
+        }
+        self.emitter.message("Installing dotfiles...".green());
 
-        // Backup existing file
-        if dest.exists() {
-            let backup = self.backup_dir.join(file_name);
-            fs::rename(&dest, &backup).context("Failed to backup existing file")?;
-            println!("{} {:?}", "Backed up:".yellow(), dest);
+        // Load and process config
+        let config = self.config()?;
+        let mappings: Vec<&FileMapping> = config
+            .files
+            .iter()
+            .filter(|mapping| topic.is_none_or(|topic| mapping.topic() == topic))
+            .collect();
+        // A topic given a --topic-target needs no mapping of its own
+        let mut unmapped_topic = None;
+        if let Some(topic) = topic {
+            let topic_path = self.dotfiles_dir.join(topic);
+            if mappings.is_empty()
+                && self.options.topic_targets.contains_key(topic)
+                && topic_path.is_dir()
+            {
+                unmapped_topic = Some((topic, topic_path));
+            } else if mappings.is_empty() {
+                anyhow::bail!("No mappings found for topic {}", topic);
+            }
         }
 
-        // Create symlink
-        #[cfg(unix)]
-        std::os::unix::fs::symlink(src, &dest).context("Failed to create symlink")?;
+        if self.options.backup_only {
+            return self.backup_only(&mappings);
+        }
+
+        // Resolve every target up front so a bad one fails before anything changes
+        for mapping in mappings.iter().filter(|mapping| self.is_enabled(mapping)) {
+            for target in mapping.targets() {
+                self.target_path(mapping, target)?;
+            }
+        }
+        self.check_duplicate_targets(config)?;
+
+        // Create backup directory
+        if !self.options.dry_run {
+            self.create_backup_dir()?;
+            match &self.options.audit_log {
+                Some(path) => self.emitter.open_audit_log(path)?,
+                None => {
+                    let audit_log = self.backup_root.join("audit.log");
+                    self.emitter.open_audit_log(&audit_log)?;
+                    self.chown(&audit_log)?;
+                }
+            }
+        }
+
+        if self.options.transactional && !self.options.dry_run {
+            *self.transaction.borrow_mut() = Some(Transaction::default());
+        }
+
+        let started = Instant::now();
+        let mut report = InstallReport::new(self.options.dry_run);
+        let mut only_missing_skipped = 0;
+        let mut skipped = BTreeMap::new();
+        if let Some((topic, topic_path)) = unmapped_topic {
+            let (result, millis) = self.timed(|| self.process_topic(topic, &topic_path));
+            match result {
+                Ok(outcome) => report.push(MappingReport {
+                    source: topic.to_string(),
+                    outcome,
+                    millis,
+                    error: None,
+                }),
+                Err(err) => return self.abort_install(report, started, topic, millis, err),
+            }
+        }
+        for mapping in mappings {
+            if let Some(token) = self.skip_token(mapping) {
+                // A failed predicate is worth mentioning even without --verbose
+                if token == "when" && !self.options.verbose {
+                    if let Some(reason) = self.skip_reason(mapping) {
+                        self.emitter
+                            .message(format!("{} {}", mapping.source, reason).dimmed());
+                    }
+                }
+                self.note_skip(&mut skipped, mapping, token);
+                continue;
+            }
+            if self.options.interactive && !self.options.dry_run {
+                match self.confirm_mapping(mapping)? {
+                    Confirmation::Apply => {}
+                    Confirmation::Skip => {
+                        self.emitter
+                            .message(format!("{} {}", "Skipped:".dimmed(), mapping.source));
+                        *skipped.entry("declined").or_default() += 1;
+                        continue;
+                    }
+                    Confirmation::SkipRemaining => {
+                        self.emitter
+                            .message("Skipping all remaining mappings".dimmed());
+                        break;
+                    }
+                }
+            }
+            if self.options.only_missing {
+                let needs_install = self
+                    .classify_mapping(mapping)?
+                    .iter()
+                    .any(|(_, _, status)| {
+                        matches!(status, TargetStatus::Missing | TargetStatus::Broken)
+                    });
+                if !needs_install {
+                    self.note_skip(&mut skipped, mapping, "already-linked");
+                    only_missing_skipped += 1;
+                    continue;
+                }
+            }
+            let (result, millis) = self.timed(|| self.install_mapping(mapping));
+            match result {
+                Ok(outcome) => {
+                    if outcome.linked.is_empty() && !outcome.skipped.is_empty() {
+                        self.note_skip(&mut skipped, mapping, "already-linked");
+                    }
+                    report.push(MappingReport {
+                        source: mapping.source.clone(),
+                        outcome,
+                        millis,
+                        error: None,
+                    })
+                }
+                Err(err) => {
+                    return self.abort_install(report, started, &mapping.source, millis, err)
+                }
+            }
+        }
+        report.total_millis = started.elapsed().as_secs_f64() * 1000.0;
+        if let Some(transaction) = self.transaction.take() {
+            transaction.commit();
+        }
 
-        #[cfg(windows)]
-        std::os::windows::fs::symlink_file(src, &dest).context("Failed to create symlink")?;
+        if self.options.time {
+            self.emitter
+                .message(format!("\n{}", "Timings:".blue().bold()));
+            for mapping in &report.mappings {
+                self.emitter.emit(&Event::Timing {
+                    source: &mapping.source,
+                    millis: mapping.millis,
+                });
+            }
+            self.emitter.emit(&Event::TotalTime {
+                millis: report.total_millis,
+            });
+        }
+        self.emitter.message(format!(
+            "{} linked, {} backed up, {} unchanged",
+            report.totals.linked, report.totals.backed_up, report.totals.skipped
+        ));
+        if !skipped.is_empty() {
+            let reasons: Vec<String> = skipped
+                .iter()
+                .map(|(token, count)| format!("{} {}", token, count))
+                .collect();
+            self.emitter.message(format!(
+                "skipped {} (reasons: {})",
+                skipped.values().sum::<usize>(),
+                reasons.join(", ")
+            ));
+        }
+        if self.options.only_missing {
+            self.emitter.message(format!(
+                "Processed {} mapping(s), skipped {} already set up",
+                report.mappings.len(),
+                only_missing_skipped
+            ));
+        }
+        self.write_report(&report)?;
+        self.write_reverse_script()?;
 
-        println!("{} {:?} -> {:?}", "Linked:".green(), src, dest);
+        self.install_secrets()?;
+        if !self.options.dry_run {
+            self.state.borrow_mut().installed_at = Some(chrono::Local::now().to_rfc3339());
+            self.save_state()?;
+            self.warn_backup_size()?;
+        }
+        // A dry run hasn't produced the secrets file yet, so don't nag about it
+        if !(self.options.dry_run && config.secrets.command.is_some()) {
+            self.check_secrets()?;
+        }
+        if self.options.verify_links && !self.options.dry_run {
+            self.verify_new_links(&report)?;
+        }
+        self.run_post_install_hooks()?;
         Ok(())
     }
 
-    pub fn check_secrets(&self) -> Result<()> {
-        let secrets_path = self.home_dir.join(".secrets");
-        if !secrets_path.exists() {
-            println!("{}", "No .secrets file found in home directory".yellow());
-            println!("Create one if you need to store sensitive information");
+    /// Installs one mapping's worth with `install`, holding its output for
+    /// --output-on-error, and says how many milliseconds it took.
+    fn timed(
+        &self,
+        install: impl FnOnce() -> Result<InstallOutcome>,
+    ) -> (Result<InstallOutcome>, f64) {
+        let started = Instant::now();
+        if self.options.output_on_error {
+            self.emitter.hold();
+        }
+        let result = install();
+        self.emitter.release(result.is_err());
+        (result, started.elapsed().as_secs_f64() * 1000.0)
+    }
+
+    /// Stops an install after `source` failed with `err`: rolls back a
+    /// transaction or saves what was done so far, then writes the report and
+    /// reverse script covering everything up to the failure.
+    fn abort_install(
+        &self,
+        mut report: InstallReport,
+        started: Instant,
+        source: &str,
+        millis: f64,
+        err: anyhow::Error,
+    ) -> Result<()> {
+        report.push(MappingReport {
+            source: source.to_string(),
+            outcome: InstallOutcome::default(),
+            millis,
+            error: Some(format!("{:#}", err)),
+        });
+        report.total_millis = started.elapsed().as_secs_f64() * 1000.0;
+        if self.transaction.borrow().is_some() {
+            self.roll_back();
+        } else {
+            // Keep the links and backups made before the failure restorable
+            self.save_state()?;
+        }
+        self.write_report(&report)?;
+        self.write_reverse_script()?;
+        Err(err)
+    }
+
+    /// Confirms every link this run created resolves to its source.
+    fn verify_new_links(&self, report: &InstallReport) -> Result<()> {
+        let linked: HashSet<&Path> = report
+            .mappings
+            .iter()
+            .flat_map(|mapping| &mapping.outcome.linked)
+            .map(PathBuf::as_path)
+            .collect();
+
+        let mut mismatched = Vec::new();
+        let mut checked = 0;
+        for link in &self.state.borrow().links {
+            if !linked.contains(link.target.as_path()) {
+                continue;
+            }
+            checked += 1;
+            match (
+                fs::canonicalize(&link.target),
+                fs::canonicalize(&link.source),
+            ) {
+                (Ok(resolved), Ok(source)) if resolved == source => {}
+                (resolved, _) => mismatched.push(format!(
+                    "{} -> {} (expected {})",
+                    link.target.display(),
+                    resolved.map_or_else(|err| err.to_string(), |path| path.display().to_string()),
+                    link.source.display()
+                )),
+            }
+        }
+
+        if !mismatched.is_empty() {
+            anyhow::bail!(
+                "{} link(s) don't resolve to their source:\n  {}",
+                mismatched.len(),
+                mismatched.join("\n  ")
+            );
         }
+        self.log(&format!("Verified {} new link(s)", checked));
         Ok(())
     }
 
-    pub fn get_target_path(&self, topic: &str, file_name: &str) -> PathBuf {
-        match topic {
-            "zellij" => self.home_dir.join(".config").join("zellij").join(file_name),
-            "nvim" => self.home_dir.join(".config").join("nvim").join(file_name),
-            _ => self.home_dir.join(file_name),
+    /// Records how to undo a change: `undo` for `--transactional`, and
+    /// `shell`, the same as shell commands, for `--reverse-script`.
+    fn journal(
+        &self,
+        description: String,
+        shell: String,
+        undo: impl FnOnce() -> Result<()> + 'static,
+    ) {
+        if self.options.reverse_script.is_some() {
+            self.reverse.borrow_mut().push(shell);
+        }
+        if let Some(transaction) = self.transaction.borrow_mut().as_mut() {
+            transaction.record(description, undo);
         }
     }
 
-    pub fn list_configs(&self) -> Result<()> {
-        println!("{}", "Current Configuration Files:".green().bold());
-        println!("{}", "=========================".green());
+    /// Undoes everything the running transactional install changed.
+    fn roll_back(&self) {
+        let Some(transaction) = self.transaction.take() else {
+            return;
+        };
+        let changes = transaction.len();
+        let failures = transaction.rollback();
+        // Moved backups went back to where they came from
+        self.manifest
+            .borrow_mut()
+            .backups
+            .retain(|record| fs::symlink_metadata(&record.backup).is_ok());
+        let manifest = self.backup_dir.join("manifest.toml");
+        let saved = if !self.manifest.borrow().backups.is_empty() {
+            self.save_manifest()
+        } else if manifest.exists() {
+            fs::remove_file(&manifest).map_err(Into::into)
+        } else {
+            Ok(())
+        };
+        if let Err(err) = saved {
+            self.emitter.emit(&Event::Warning {
+                message: &format!("Failed to update {}: {:#}", manifest.display(), err),
+            });
+        }
+        // Nothing is left for the script to undo
+        self.reverse.borrow_mut().clear();
+        for (description, err) in &failures {
+            self.emitter.emit(&Event::Warning {
+                message: &format!("Failed to undo {}: {:#}", description, err),
+            });
+        }
+        self.emitter.message(format!(
+            "{} {} change(s)",
+            "Rolled back".yellow(),
+            changes - failures.len()
+        ));
+    }
 
-        let config = self.load_config()?;
+    fn write_report(&self, report: &InstallReport) -> Result<()> {
+        if let Some(path) = &self.options.report {
+            report.write(path, self.options.report_format)?;
+            self.log(&format!("Wrote report to {}", path.display()));
+        }
+        Ok(())
+    }
 
-        for mapping in config.files {
-            let source = self.dotfiles_dir.join(&mapping.source);
-            let target = self.home_dir.join(&mapping.target);
+    /// Writes the `--reverse-script`: the undo commands for every change
+    /// this run made, newest first.
+    fn write_reverse_script(&self) -> Result<()> {
+        let Some(path) = &self.options.reverse_script else {
+            return Ok(());
+        };
+        let mut script = String::from("#!/bin/sh\n");
+        if self.options.dry_run {
+            script.push_str("# Dry run: install changed nothing, so there is nothing to undo\n");
+        } else {
+            script.push_str(&format!(
+                "# Undoes `dotfiles install` run at {}\n",
+                chrono::Local::now().to_rfc3339()
+            ));
+            for step in self.reverse.borrow().iter().rev() {
+                script.push_str(step);
+                script.push('\n');
+            }
+        }
+        write_atomic_with_mode(path, script.as_bytes(), Some(0o755))?;
+        self.log(&format!("Wrote reverse script to {}", path.display()));
+        Ok(())
+    }
 
-            println!("\n{}:", mapping.source.blue().bold());
-            println!("  Source: {}", source.display());
-            println!("  Target: {}", target.display());
-            println!(
-                "  Status: {}",
-                if target.exists() {
-                    "Installed".green()
-                } else {
-                    "Not installed".yellow()
+    /// Rejects configs where two enabled mappings (or two targets of one
+    /// mapping) would fight over the same path, however the targets are
+    /// spelled: `~/.zshrc` is `.zshrc`, and `.config/` for source `nvim` is
+    /// `.config/nvim`. Mappings that are never active together, like per-OS
+    /// alternatives or a disabled fallback, may share a target.
+    fn check_duplicate_targets(&self, config: &Config) -> Result<()> {
+        let mut seen: HashMap<PathBuf, &str> = HashMap::new();
+        for mapping in config.files.iter().filter(|m| self.is_enabled(m)) {
+            for target in mapping.targets() {
+                // Targets of the mappings being installed were checked already
+                let Ok(path) = self.target_path(mapping, target) else {
+                    continue;
+                };
+                match seen.get(&path) {
+                    Some(&other) if other == target => {
+                        anyhow::bail!("Target {} is used by more than one mapping", target)
+                    }
+                    Some(&other) => anyhow::bail!(
+                        "Targets {} and {} are the same path, used by more than one mapping",
+                        other,
+                        target
+                    ),
+                    None => {
+                        seen.insert(path, target);
+                    }
                 }
-            );
-            println!("  {}", "-".repeat(50));
+            }
         }
         Ok(())
     }
 
-    pub fn check_status(&self) -> Result<()> {
-        println!("{}", "Configuration Status:".green().bold());
-        println!("{}", "===================".green());
+    /// Backs up and links a single mapping. Callers driving mappings one at a
+    /// time should call [`DotfileManager::save_state`] once they are done.
+    pub fn install_mapping(&self, mapping: &FileMapping) -> Result<InstallOutcome> {
+        let source = self.source_path(mapping)?;
+        let settings = self.settings_for(Some(mapping));
+        let mut outcome = InstallOutcome::default();
 
-        let config = self.load_config()?;
-        let mut all_good = true;
+        let generated = self.generated_output(mapping)?;
+        if mapping.readonly && generated.is_none() && settings.strategy == Strategy::Link {
+            self.emitter.emit(&Event::Warning {
+                message: &format!(
+                    "readonly has no effect on {}: it is linked, so the source's permissions apply",
+                    mapping.source
+                ),
+            });
+        }
 
-        for mapping in config.files {
-            let target = self.home_dir.join(&mapping.target);
-            if !target.exists() {
-                println!("{} is not installed", mapping.source);
-                all_good = false;
+        if mapping.is_glob() {
+            for target in mapping.targets() {
+                let dir = self.target_path(mapping, target)?;
+                let files = self.glob_files(mapping, &dir)?;
+                if files.is_empty() {
+                    self.emitter.emit(&Event::Warning {
+                        message: &format!(
+                            "source {} matches nothing; nothing linked",
+                            mapping.source
+                        ),
+                    });
+                    break;
+                }
+                self.ensure_target_dir(&dir)?;
+                for (file, target) in files {
+                    let action = self.place(&file, &target, &settings)?;
+                    outcome.record(target, action);
+                }
             }
+            return Ok(outcome);
         }
 
-        if all_good {
-            println!("\n{}", "All configurations are installed!".green());
+        if let Some(contents) = generated {
+            for target in mapping.targets() {
+                let target = self.target_path(mapping, target)?;
+                if let Some(parent) = target.parent() {
+                    self.ensure_target_dir(parent)?;
+                }
+                if let Some(action) = self.skip_existing(&source, &target, &settings) {
+                    outcome.record(target, action);
+                    continue;
+                }
+                if mapping.concat {
+                    self.report_changed_fragments(&source, &target)?;
+                }
+                let action = self.write_output(&source, &target, &contents, &settings, true)?;
+                outcome.record(target, action);
+            }
+            return Ok(outcome);
         }
 
-        Ok(())
+        if source.is_dir() && is_empty_dir(&source)? {
+            self.emitter.emit(&Event::Warning {
+                message: &format!("source {} is empty; nothing linked", mapping.source),
+            });
+        }
+
+        for target in mapping.targets() {
+            let target = self.target_path(mapping, target)?;
+
+            if source.is_dir() {
+                self.ensure_target_dir(&target)?;
+                self.copy_dir_mode(&source, &target, &settings)?;
+                outcome.merge(self.process_directory_with(&source, &target, &settings, 0)?);
+            } else {
+                if let Some(parent) = target.parent() {
+                    self.ensure_target_dir(parent)?;
+                }
+                let action = self.place(&source, &target, &settings)?;
+                outcome.record(target, action);
+            }
+        }
+
+        Ok(outcome)
     }
 
-    pub fn add_config(&self, topic: &str, file: &Path) -> Result<()> {
-        if !file.exists() {
-            anyhow::bail!("File does not exist: {:?}", file);
+    /// Previews what installing `mapping` would change, then asks whether to
+    /// go ahead. Mappings with nothing to change are applied without asking.
+    fn confirm_mapping(&self, mapping: &FileMapping) -> Result<Confirmation> {
+        self.emitter
+            .message(format!("\n{} {}", "Mapping:".blue().bold(), mapping.source));
+        self.planning.set(true);
+        let planned = self.install_mapping(mapping);
+        self.planning.set(false);
+        if planned?.linked.is_empty() {
+            return Ok(Confirmation::Apply);
         }
 
-        let topic_dir = self.dotfiles_dir.join(topic);
-        fs::create_dir_all(&topic_dir)?;
+        let choice = dialoguer::Select::new()
+            .with_prompt("Apply")
+            .items(["Apply", "Skip", "Skip all remaining"])
+            .default(0)
+            .interact()?;
+        Ok(match choice {
+            0 => Confirmation::Apply,
+            1 => Confirmation::Skip,
+            _ => Confirmation::SkipRemaining,
+        })
+    }
 
-        let file_name = file.file_name().context("Invalid file name")?;
-        let dest = topic_dir.join(file_name);
+    /// Whether changes are only being planned, for `--dry-run` or while
+    /// `--interactive` previews a mapping.
+    fn dry_run(&self) -> bool {
+        self.options.dry_run || self.planning.get()
+    }
 
-        fs::copy(file, &dest)?;
-        println!(
-            "Added {} to {} configuration",
-            file_name.to_string_lossy(),
-            topic
-        );
+    /// How many warnings this run has printed so far.
+    pub fn warning_count(&self) -> usize {
+        self.emitter.warning_count()
+    }
 
-        Ok(())
+    /// Where a mapping's source lives: the first `source_roots` entry that
+    /// has it, falling back to the dotfiles directory.
+    pub fn source_path(&self, mapping: &FileMapping) -> Result<PathBuf> {
+        if let Some(path) = self.sources.borrow().get(&mapping.source) {
+            return Ok(path.clone());
+        }
+        let mut roots: Vec<PathBuf> = self
+            .config()?
+            .source_roots
+            .iter()
+            .map(|root| match root.strip_prefix("~/") {
+                Some(relative) => self.home_dir.join(relative),
+                // Collecting the components drops any `.` in the path
+                None => self.dotfiles_dir.join(root).components().collect(),
+            })
+            .collect();
+        roots.push(self.dotfiles_dir.clone());
+
+        // A glob is looked up by the directory it matches in
+        let probe = if mapping.is_glob() {
+            mapping.source_base()
+        } else {
+            PathBuf::from(&mapping.source)
+        };
+        let found = roots
+            .iter()
+            .find(|root| fs::symlink_metadata(root.join(&probe)).is_ok());
+        let path = match found {
+            Some(root) => {
+                self.log(&format!("{} found in {}", mapping.source, root.display()));
+                root.join(&mapping.source)
+            }
+            None => self.dotfiles_dir.join(&mapping.source),
+        };
+        self.sources
+            .borrow_mut()
+            .insert(mapping.source.clone(), path.clone());
+        Ok(path)
+    }
+
+    /// The files a glob source matches, each paired with its target: its
+    /// name inside `dir`. Two matches with the same name are an error, since
+    /// one would silently replace the other.
+    fn glob_files(&self, mapping: &FileMapping, dir: &Path) -> Result<Vec<(PathBuf, PathBuf)>> {
+        let mut matches = vec![PathBuf::new()];
+        for component in self.source_path(mapping)?.components() {
+            let segment = component.as_os_str().to_string_lossy();
+            if !segment.contains(['*', '?']) {
+                for path in &mut matches {
+                    path.push(component);
+                }
+                continue;
+            }
+            let mut expanded = Vec::new();
+            for path in &matches {
+                let Ok(entries) = fs::read_dir(path) else {
+                    continue;
+                };
+                for entry in entries {
+                    let entry = entry?;
+                    if glob_match(&segment, &entry.file_name().to_string_lossy()) {
+                        expanded.push(entry.path());
+                    }
+                }
+            }
+            matches = expanded;
+        }
+        matches.retain(|path| path.is_file());
+        matches.sort();
+
+        let mut by_name: HashMap<&OsStr, &Path> = HashMap::new();
+        for file in &matches {
+            let name = file.file_name().context("Invalid glob match")?;
+            if let Some(other) = by_name.insert(name, file) {
+                anyhow::bail!(
+                    "{} matches both {} and {}, which would both go to {}",
+                    mapping.source,
+                    other.display(),
+                    file.display(),
+                    dir.join(name).display()
+                );
+            }
+        }
+        Ok(matches
+            .iter()
+            .map(|file| (file.clone(), dir.join(file.file_name().unwrap_or_default())))
+            .collect())
+    }
+
+    /// The file contents a templated or concatenated mapping produces, or
+    /// `None` for mappings that link or copy their source as is.
+    fn generated_output(&self, mapping: &FileMapping) -> Result<Option<Vec<u8>>> {
+        let source = self.source_path(mapping)?;
+        if mapping.is_glob() && (mapping.template || mapping.concat) {
+            anyhow::bail!(
+                "Mapping {} has a glob source, which can't be templated or concatenated",
+                mapping.source
+            );
+        }
+        if mapping.template && mapping.concat {
+            anyhow::bail!(
+                "Mapping {} can't set both template and concat",
+                mapping.source
+            );
+        }
+        if mapping.template {
+            return Ok(Some(self.render_template(&source)?.into_bytes()));
+        }
+        if !mapping.concat {
+            return Ok(None);
+        }
+
+        if !source.is_dir() {
+            anyhow::bail!("Concat source must be a directory: {}", source.display());
+        }
+        let mut contents = Vec::new();
+        for fragment in fragments(&source)? {
+            contents.extend(
+                fs::read(&fragment)
+                    .with_context(|| format!("Failed to read {}", fragment.display()))?,
+            );
+        }
+        Ok(Some(contents))
+    }
+
+    /// Mentions each fragment modified since `target` was last written.
+    fn report_changed_fragments(&self, source: &Path, target: &Path) -> Result<()> {
+        let Ok(written) = fs::metadata(target).and_then(|m| m.modified()) else {
+            return Ok(());
+        };
+        for fragment in fragments(source)? {
+            if fs::metadata(&fragment)
+                .and_then(|m| m.modified())
+                .is_ok_and(|modified| modified > written)
+            {
+                self.emitter.message(format!(
+                    "{} {}",
+                    "Fragment changed:".blue(),
+                    fragment.display()
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn settings_for(&self, mapping: Option<&FileMapping>) -> MappingSettings {
+        MappingSettings {
+            backup: !self.options.no_backup && mapping.and_then(|m| m.backup).unwrap_or(true),
+            strategy: mapping.map(FileMapping::strategy).unwrap_or_default(),
+            mode: mapping.and_then(|m| m.mode),
+            readonly: mapping.is_some_and(|m| m.readonly),
+            max_depth: mapping.and_then(|m| m.max_depth).or(self.options.max_depth),
+            rename: mapping.map(|m| m.rename.clone()).unwrap_or_default(),
+            skip_if_exists: mapping.is_some_and(|m| m.skip_if_exists),
+        }
+    }
+
+    /// Creates a directory targets will be placed in, unless `--no-create-dirs`
+    /// asked for it to already exist.
+    fn ensure_target_dir(&self, dir: &Path) -> Result<()> {
+        if dir.is_dir() {
+            return Ok(());
+        }
+        // A directory that doesn't resolve may be a link cycle, which can't
+        // be created or descended into
+        if let Some(cycle) = cycle_above(dir) {
+            anyhow::bail!(
+                "{} is in a symlink cycle ({}); not descending into it",
+                self.display_target(dir),
+                self.describe_cycle(&cycle)
+            );
+        }
+        if self.options.no_create_dirs {
+            anyhow::bail!(
+                "Target directory {} does not exist (not creating it because of --no-create-dirs)",
+                dir.display()
+            );
+        }
+        if self.dry_run() {
+            return Ok(());
+        }
+        let created: Vec<PathBuf> = dir
+            .ancestors()
+            .take_while(|ancestor| !ancestor.exists())
+            .map(Path::to_path_buf)
+            .collect();
+        fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create directory {}", dir.display()))?;
+        {
+            let mut state = self.state.borrow_mut();
+            for created in &created {
+                if !state.created_dirs.contains(created) {
+                    state.created_dirs.push(created.clone());
+                }
+            }
+        }
+        for created in created.iter().rev() {
+            self.chown(created)?;
+        }
+        let shell = created
+            .iter()
+            .map(|dir| format!("rmdir {}", shell_quote(dir)))
+            .collect::<Vec<_>>()
+            .join("\n");
+        self.journal(format!("creating {}", dir.display()), shell, move || {
+            for dir in &created {
+                fs::remove_dir(dir)
+                    .with_context(|| format!("Failed to remove {}", dir.display()))?;
+            }
+            Ok(())
+        });
+        Ok(())
+    }
+
+    /// Creates this run's backup directory (and the backup root) with
+    /// restrictive permissions.
+    fn create_backup_dir(&self) -> Result<()> {
+        create_dir_all_with_mode(&self.backup_dir, self.options.backup_mode.unwrap_or(0o700))
+            .context("Failed to create backup directory")?;
+        self.chown(&self.backup_root)?;
+        self.chown(&self.backup_dir)
+    }
+
+    /// Persists the recorded links and, if anything was backed up, this run's manifest.
+    pub fn save_state(&self) -> Result<()> {
+        self.state.borrow().save(&self.state_path())?;
+        self.chown(&self.state_path())?;
+        if !self.manifest.borrow().backups.is_empty() {
+            self.save_manifest()?;
+        }
+        Ok(())
+    }
+
+    /// Rewrites this run's manifest in place, atomically, so every backup
+    /// made so far can be restored even if the run dies before finishing.
+    fn save_manifest(&self) -> Result<()> {
+        let path = self.backup_dir.join("manifest.toml");
+        self.manifest.borrow().save(&path)?;
+        self.chown(&path)
+    }
+
+    pub fn process_topic(&self, topic: &str, topic_path: &Path) -> Result<InstallOutcome> {
+        self.emitter
+            .message(format!("{} {}", "Processing topic:".green(), topic));
+
+        let target_dir = self.topic_dir(topic);
+
+        // Create target directory if it doesn't exist
+        self.ensure_target_dir(&target_dir)?;
+
+        // Process all files in the topic directory
+        self.process_directory(topic_path, &target_dir)
+    }
+
+    pub fn process_directory(&self, src_dir: &Path, target_dir: &Path) -> Result<InstallOutcome> {
+        self.process_directory_with(src_dir, target_dir, &self.settings_for(None), 0)
+    }
+
+    /// Places every file under `src_dir`, `depth` levels below the mapping's
+    /// source. Subdirectories past `max_depth` are linked as a whole, or
+    /// skipped when copying.
+    fn process_directory_with(
+        &self,
+        src_dir: &Path,
+        target_dir: &Path,
+        settings: &MappingSettings,
+        depth: usize,
+    ) -> Result<InstallOutcome> {
+        let mut outcome = InstallOutcome::default();
+
+        // A target directory that is itself a link to the source already
+        // makes every file in it correct; linking file by file through it
+        // would back up the repo's own files
+        if settings.strategy == Strategy::Link && links_to(target_dir, src_dir) {
+            self.log(&format!("Already linked as a directory: {:?}", target_dir));
+            let mut files = Vec::new();
+            collect_files(src_dir, target_dir, None, &mut files)?;
+            for (source, target) in files {
+                self.emitter.emit(&Event::Skipped {
+                    source: &source,
+                    target: &target,
+                });
+                outcome.skipped.push(target);
+            }
+            return Ok(outcome);
+        }
+
+        let filter = DirFilter::load(src_dir)?;
+        for entry in fs::read_dir(src_dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            if entry.file_name() == TopicMeta::FILE_NAME {
+                continue;
+            }
+            if !filter.allows(&entry.file_name().to_string_lossy()) {
+                self.log(&format!("Skipping {:?}: filtered out", path));
+                continue;
+            }
+
+            if path.is_file() {
+                let dest = settings.file_target(target_dir, &entry.file_name());
+                let action = self.place(&path, &dest, settings)?;
+                outcome.record(dest, action);
+            } else if loops_back(src_dir, &path) {
+                self.emitter.emit(&Event::Warning {
+                    message: &format!(
+                        "{} links back to {}; not descending into it",
+                        path.display(),
+                        fs::canonicalize(&path).unwrap_or_default().display()
+                    ),
+                });
+            } else if path.is_dir() {
+                let new_target = target_dir.join(path.file_name().unwrap());
+                if settings.max_depth.is_some_and(|max| depth >= max) {
+                    if settings.strategy == Strategy::Link {
+                        let action = self.link_with(&path, &new_target, settings)?;
+                        outcome.record(new_target, action);
+                    } else {
+                        self.log(&format!("Skipping {:?}: deeper than max_depth", path));
+                    }
+                    continue;
+                }
+                self.ensure_target_dir(&new_target)?;
+                self.copy_dir_mode(&path, &new_target, settings)?;
+                outcome.merge(self.process_directory_with(
+                    &path,
+                    &new_target,
+                    settings,
+                    depth + 1,
+                )?);
+            }
+        }
+
+        if !outcome.skipped.is_empty() {
+            self.log(&format!(
+                "{} file(s) in {:?} already linked",
+                outcome.skipped.len(),
+                target_dir
+            ));
+        }
+        Ok(outcome)
+    }
+
+    /// Gives a directory copied from the repo the same permissions as its
+    /// source, like the files in it get.
+    fn copy_dir_mode(&self, src: &Path, dest: &Path, settings: &MappingSettings) -> Result<()> {
+        if settings.strategy != Strategy::Copy || self.dry_run() {
+            return Ok(());
+        }
+        #[cfg(unix)]
+        if let Some(mode) = file_mode(src) {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(dest, fs::Permissions::from_mode(mode))
+                .with_context(|| format!("Failed to set permissions on {}", dest.display()))?;
+        }
+        #[cfg(not(unix))]
+        let _ = (src, dest);
+        Ok(())
+    }
+
+    pub fn link_file(&self, src: &Path, target_dir: &Path) -> Result<LinkAction> {
+        let file_name = src.file_name().context("Invalid file name")?;
+        self.link_to(src, &target_dir.join(file_name))
+    }
+
+    /// Links `src` at exactly `dest`, backing up whatever was there first.
+    pub fn link_to(&self, src: &Path, dest: &Path) -> Result<LinkAction> {
+        self.link_with(src, dest, &self.settings_for(None))
+    }
+
+    /// Links or copies `src` to `dest` according to the mapping's strategy.
+    fn place(&self, src: &Path, dest: &Path, settings: &MappingSettings) -> Result<LinkAction> {
+        if let Some(action) = self.skip_existing(src, dest, settings) {
+            return Ok(action);
+        }
+        match settings.strategy {
+            Strategy::Link => self.link_with(src, dest, settings),
+            Strategy::Copy => {
+                let contents =
+                    fs::read(src).with_context(|| format!("Failed to read {}", src.display()))?;
+                // Keep each copied file's own mode (e.g. executable scripts)
+                // unless the mapping sets one
+                let settings = MappingSettings {
+                    mode: settings.mode.or_else(|| file_mode(src)),
+                    ..settings.clone()
+                };
+                self.write_output(src, dest, &contents, &settings, false)
+            }
+        }
+    }
+
+    /// Leaves an existing `skip_if_exists` target untouched: no backup, no
+    /// link. Returns `None` when the target should be placed as usual.
+    fn skip_existing(
+        &self,
+        src: &Path,
+        dest: &Path,
+        settings: &MappingSettings,
+    ) -> Option<LinkAction> {
+        if !settings.skip_if_exists || fs::symlink_metadata(dest).is_err() {
+            return None;
+        }
+        if self.dry_run() {
+            return Some(self.plan_change(src, dest, TargetStatus::Present, settings));
+        }
+        self.emitter
+            .message(format!("{} {:?}", "Skipped (exists):".dimmed(), dest));
+        Some(LinkAction::Skipped)
+    }
+
+    fn link_with(&self, src: &Path, dest: &Path, settings: &MappingSettings) -> Result<LinkAction> {
+        if self.dry_run() {
+            let status = match classify(src, dest) {
+                // Reaching the source by another path still means relinking
+                TargetStatus::Linked if !fs::read_link(dest).is_ok_and(|p| p == src) => {
+                    TargetStatus::WrongLink
+                }
+                status => status,
+            };
+            return Ok(self.plan_change(src, dest, status, settings));
+        }
+
+        // Nothing to do if the link is already in place
+        if fs::read_link(dest).is_ok_and(|points_to| points_to == src) {
+            self.emitter.emit(&Event::Skipped {
+                source: src,
+                target: dest,
+            });
+            self.state.borrow_mut().record_link(src, dest);
+            return Ok(LinkAction::Skipped);
+        }
+
+        let file_name = dest.file_name().context("Invalid file name")?;
+        self.log(&format!("Processing file: {:?}", file_name));
+
+        // A link reaching the source by another path (e.g. through a
+        // symlinked dotfiles dir) is ours; repoint it without a backup
+        if links_to(dest, src) {
+            let points_to = fs::read_link(dest).context("Failed to read old symlink")?;
+            fs::remove_file(dest).context("Failed to remove old symlink")?;
+            let dest = dest.to_path_buf();
+            let shell = format!("ln -s {} {}", shell_quote(&points_to), shell_quote(&dest));
+            self.journal(format!("removing {}", dest.display()), shell, move || {
+                symlink(&points_to, &dest)
+            });
+        }
+
+        // Backup existing file
+        let action = if self.backup_existing(dest, settings)? {
+            LinkAction::BackedUpAndLinked
+        } else {
+            LinkAction::Linked
+        };
+
+        // Create symlink
+        replace_with_symlink(src, dest)?;
+        self.chown(dest)?;
+        let link = dest.to_path_buf();
+        let shell = format!("rm {}", shell_quote(&link));
+        self.journal(format!("linking {}", link.display()), shell, move || {
+            Ok(fs::remove_file(&link)?)
+        });
+
+        self.emitter.emit(&Event::Linked {
+            source: src,
+            target: dest,
+        });
+        self.state.borrow_mut().record_link(src, dest);
+        Ok(action)
+    }
+
+    /// Reports what installing `src` at `dest` would change, given the
+    /// target's current status, and returns the action install would take.
+    fn plan_change(
+        &self,
+        src: &Path,
+        dest: &Path,
+        status: TargetStatus,
+        settings: &MappingSettings,
+    ) -> LinkAction {
+        let replaced = match status {
+            TargetStatus::Linked => "no change (already linked)".to_string(),
+            TargetStatus::UpToDate => "no change (up to date)".to_string(),
+            TargetStatus::Present => "no change (exists, not managed)".to_string(),
+            TargetStatus::Missing => "will create (new)".to_string(),
+            TargetStatus::WrongLink | TargetStatus::Broken => {
+                let points_to = fs::read_link(dest).unwrap_or_default();
+                format!("replace symlink {}", points_to.display())
+            }
+            TargetStatus::Adoptable | TargetStatus::Conflict if dest.is_dir() => {
+                "replace directory".to_string()
+            }
+            TargetStatus::Adoptable | TargetStatus::Conflict => "replace file".to_string(),
+        };
+
+        let (change, action) = match status {
+            TargetStatus::Linked | TargetStatus::UpToDate | TargetStatus::Present => {
+                (replaced, LinkAction::Skipped)
+            }
+            TargetStatus::Missing => (replaced, LinkAction::Linked),
+            // Our own link reached by another path is repointed, not backed up
+            TargetStatus::WrongLink if links_to(dest, src) => {
+                (format!("will {}", replaced), LinkAction::Linked)
+            }
+            _ if settings.backup => (
+                format!("will back up and {}", replaced),
+                LinkAction::BackedUpAndLinked,
+            ),
+            _ => (format!("will {} (no backup)", replaced), LinkAction::Linked),
+        };
+
+        self.emitter.emit(&Event::Delta {
+            change: &change,
+            source: src,
+            target: dest,
+        });
+        action
+    }
+
+    /// Saves whatever exists at `dest` into this run's backup directory,
+    /// either moving it or (with `--backup-strategy copy`) copying it and
+    /// leaving the original for the link step to replace.
+    /// Mappings with backups turned off have the target removed instead.
+    /// Returns whether anything was backed up.
+    fn backup_existing(&self, dest: &Path, settings: &MappingSettings) -> Result<bool> {
+        let Ok(metadata) = fs::symlink_metadata(dest) else {
+            return Ok(false);
+        };
+
+        // A transaction needs the target back if it rolls back, so it always
+        // keeps a backup
+        if !settings.backup && self.transaction.borrow().is_none() {
+            if metadata.is_dir() {
+                fs::remove_dir_all(dest)
+            } else {
+                fs::remove_file(dest)
+            }
+            .context("Failed to remove existing target")?;
+            self.emitter.emit(&Event::Removed { target: dest });
+            if self.options.reverse_script.is_some() {
+                self.reverse
+                    .borrow_mut()
+                    .push(format!("# cannot restore {}: no backup", dest.display()));
+            }
+            return Ok(false);
+        }
+
+        self.save_backup(dest, self.options.backup_strategy)?;
+        Ok(true)
+    }
+
+    /// Where `dest` is saved in this run's backup directory, according to
+    /// `--backup-layout`.
+    fn backup_path(&self, dest: &Path) -> Result<PathBuf> {
+        let file_name = dest.file_name().context("Invalid file name")?;
+        if self.options.backup_layout == BackupLayout::Flat {
+            return Ok(self.backup_dir.join(file_name));
+        }
+        let relative: PathBuf = match dest.strip_prefix(&self.home_dir) {
+            Ok(relative) => relative.to_path_buf(),
+            Err(_) => dest
+                .components()
+                .filter(|component| matches!(component, Component::Normal(_)))
+                .collect(),
+        };
+        Ok(self.backup_dir.join(relative))
+    }
+
+    /// Moves or copies `dest` into this run's backup directory and records
+    /// it in the manifest. Returns where the backup went.
+    fn save_backup(&self, dest: &Path, strategy: BackupStrategy) -> Result<PathBuf> {
+        self.create_backup_dir()?;
+        let backup = self.backup_path(dest)?;
+        let parent = backup.parent().context("Invalid backup path")?;
+        if parent != self.backup_dir {
+            create_dir_all_with_mode(parent, self.options.backup_mode.unwrap_or(0o700))
+                .context("Failed to create backup directory")?;
+            for dir in parent.ancestors().take_while(|dir| *dir != self.backup_dir) {
+                self.chown(dir)?;
+            }
+        }
+        match strategy {
+            BackupStrategy::Move => {
+                fs::rename(dest, &backup).context("Failed to backup existing file")?
+            }
+            BackupStrategy::Copy => {
+                copy_recursive(dest, &backup).context("Failed to backup existing file")?
+            }
+        }
+        self.chown(&backup)?;
+        // Make sure the backup is durable before the original path is reused
+        sync_dir(parent)?;
+        let (original, saved) = (dest.to_path_buf(), backup.clone());
+        let shell = format!(
+            "[ -e {0} ] || [ -L {0} ] || mv {1} {0}",
+            shell_quote(&original),
+            shell_quote(&saved)
+        );
+        self.journal(format!("backing up {}", dest.display()), shell, move || {
+            // A copied backup whose original was never replaced has nothing to restore
+            if fs::symlink_metadata(&original).is_err() {
+                fs::rename(&saved, &original)?;
+            }
+            Ok(())
+        });
+        self.emitter.emit(&Event::BackedUp {
+            target: dest,
+            backup: &backup,
+        });
+        self.manifest.borrow_mut().backups.push(BackupRecord {
+            original: dest.to_path_buf(),
+            backup: backup.clone(),
+        });
+        // Before the original path gets replaced
+        self.save_manifest()?;
+        Ok(backup)
+    }
+
+    /// The name this machine goes by: `--machine`, then `$DOTFILES_MACHINE`,
+    /// then the real hostname.
+    pub fn machine(&self) -> String {
+        self.options
+            .machine
+            .clone()
+            .or_else(|| env::var("DOTFILES_MACHINE").ok().filter(|m| !m.is_empty()))
+            .unwrap_or_else(|| gethostname::gethostname().to_string_lossy().into_owned())
+    }
+
+    /// Renders a templated source, resolving names with
+    /// [`DotfileManager::lookup_var`].
+    pub fn render_template(&self, source: &Path) -> Result<String> {
+        if source.is_dir() {
+            anyhow::bail!("Template source must be a file: {}", source.display());
+        }
+        let vars = &self.config()?.vars;
+        let contents = fs::read_to_string(source)
+            .with_context(|| format!("Failed to read template {}", source.display()))?;
+
+        template::render(&contents, |name| self.lookup_var(vars, name))
+            .with_context(|| format!("Failed to render {}", source.display()))
+    }
+
+    /// Resolves a template name from `[vars]`, then the built-ins `hostname`
+    /// and `user`, then environment variables.
+    fn lookup_var(&self, vars: &BTreeMap<String, String>, name: &str) -> Option<String> {
+        vars.get(name)
+            .cloned()
+            .or_else(|| match name {
+                "hostname" => Some(self.machine()),
+                "user" => current_user(),
+                _ => None,
+            })
+            .or_else(|| env::var(name).ok())
+    }
+
+    /// A target with its template placeholders filled in.
+    fn expand_target(&self, target: &str) -> Result<String> {
+        if !target.contains("{{") {
+            return Ok(target.to_string());
+        }
+        let vars = &self.config()?.vars;
+        template::render(target, |name| self.lookup_var(vars, name))
+            .with_context(|| format!("Failed to expand target {}", target))
+    }
+
+    /// Where a mapping target lands under home. Targets may use template
+    /// placeholders such as `{{user}}` to get per-user or per-host paths, and
+    /// a trailing `/` places the source inside that directory.
+    /// Targets must stay inside the home directory unless the mapping sets
+    /// `allow_outside_home` or `--allow-outside-home` was passed. Targets
+    /// inside the dotfiles repo itself are always rejected, since linking
+    /// there would point files at themselves or move repo files into backups.
+    pub fn target_path(&self, mapping: &FileMapping, target: &str) -> Result<PathBuf> {
+        let expanded = self.expand_target(target)?;
+
+        let allowed = self.options.allow_outside_home || mapping.allow_outside_home;
+        if !allowed && escapes_root(Path::new(&expanded)) {
+            anyhow::bail!(
+                "Target {} resolves to {}, which is outside the home directory \
+                 (set allow_outside_home = true on the mapping or pass --allow-outside-home)",
+                target,
+                expanded
+            );
+        }
+        let mut path = self.home_dir.join(&expanded);
+        // A glob's target is always the directory its matches go into
+        if expanded.ends_with('/') && !mapping.is_glob() {
+            path.push(mapping.source_name());
+        }
+
+        if is_within(&path, &self.dotfiles_dir) {
+            anyhow::bail!(
+                "Target {} resolves to {}, which is inside the dotfiles directory {}",
+                target,
+                path.display(),
+                self.dotfiles_dir.display()
+            );
+        }
+        Ok(path)
+    }
+
+    /// Writes `contents` (generated from `src` when `rendered` is set,
+    /// otherwise a plain copy of `src`) to `dest`, backing up anything that
+    /// differs from it first.
+    fn write_output(
+        &self,
+        src: &Path,
+        dest: &Path,
+        contents: &[u8],
+        settings: &MappingSettings,
+        rendered: bool,
+    ) -> Result<LinkAction> {
+        self.log(&format!(
+            "{} {:?}",
+            if rendered {
+                "Generating from:"
+            } else {
+                "Copying file:"
+            },
+            src
+        ));
+
+        let status = classify_copy(contents, dest);
+        if self.dry_run() {
+            return Ok(self.plan_change(src, dest, status, settings));
+        }
+
+        if status == TargetStatus::UpToDate {
+            self.log(&format!("Already up to date: {:?}", dest));
+            if let Some(mode) = settings.mode {
+                set_mode(dest, mode)?;
+            }
+            if settings.readonly {
+                set_readonly(dest)?;
+            }
+            self.emitter.emit(&Event::Skipped {
+                source: src,
+                target: dest,
+            });
+            return Ok(LinkAction::Skipped);
+        }
+
+        let action = if self.backup_existing(dest, settings)? {
+            LinkAction::BackedUpAndLinked
+        } else {
+            LinkAction::Linked
+        };
+
+        if fs::symlink_metadata(dest).is_ok_and(|metadata| metadata.is_dir()) {
+            fs::remove_dir_all(dest).context("Failed to remove replaced directory")?;
+        }
+        write_atomic_with_mode(dest, contents, settings.mode)
+            .with_context(|| format!("Failed to write {}", dest.display()))?;
+        self.chown(dest)?;
+        let written = dest.to_path_buf();
+        let shell = format!("rm {}", shell_quote(&written));
+        self.journal(format!("writing {}", written.display()), shell, move || {
+            Ok(fs::remove_file(&written)?)
+        });
+        // Creation modes are masked by the umask; set the exact one asked for
+        if let Some(mode) = settings.mode {
+            set_mode(dest, mode)?;
+        }
+        if settings.readonly {
+            set_readonly(dest)?;
+        }
+        if rendered {
+            self.emitter.emit(&Event::Rendered {
+                source: src,
+                target: dest,
+            });
+        } else {
+            self.emitter.emit(&Event::Copied {
+                source: src,
+                target: dest,
+            });
+        }
+        Ok(action)
+    }
+
+    /// Runs `[secrets] command` and writes its output to the secrets target,
+    /// readable only by the owner.
+    pub fn install_secrets(&self) -> Result<()> {
+        let secrets = &self.config()?.secrets;
+        let Some(command) = &secrets.command else {
+            return Ok(());
+        };
+
+        let target = self.home_dir.join(secrets.target());
+        if target.starts_with(&self.dotfiles_dir) {
+            anyhow::bail!(
+                "Refusing to write secrets inside the dotfiles repo: {}",
+                target.display()
+            );
+        }
+
+        if self.options.dry_run {
+            self.emitter.emit(&Event::Planned {
+                action: "write secrets",
+                source: None,
+                target: &target,
+            });
+            return Ok(());
+        }
+
+        self.log(&format!("Running secrets command: {}", command));
+        let output = shell_command(command)
+            .output()
+            .with_context(|| format!("Failed to run secrets command `{}`", command))?;
+        if !output.status.success() {
+            anyhow::bail!(
+                "Secrets command `{}` failed ({}): {}",
+                command,
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+
+        if let Some(parent) = target.parent() {
+            self.ensure_target_dir(parent)?;
+        }
+        write_atomic_with_mode(&target, &output.stdout, Some(0o600))
+            .context("Failed to write secrets file")?;
+        self.chown(&target)?;
+        self.emitter
+            .message(format!("{} {:?}", "Wrote secrets:".green(), target));
+        Ok(())
+    }
+
+    /// Required secret files that don't exist, or none when secret checks are
+    /// turned off.
+    pub fn missing_secrets(&self) -> Result<Vec<PathBuf>> {
+        let secrets = &self.config()?.secrets;
+        if self.options.no_secrets_check || secrets.enabled == Some(false) {
+            return Ok(Vec::new());
+        }
+        Ok(secrets
+            .required_paths(&self.home_dir)
+            .into_iter()
+            .filter(|path| !path.exists())
+            .collect())
+    }
+
+    /// Warns about each missing required secret, or fails with
+    /// `--strict-secrets`.
+    pub fn check_secrets(&self) -> Result<()> {
+        let missing = self.missing_secrets()?;
+        for path in &missing {
+            self.emitter.emit(&Event::Warning {
+                message: &format!("Missing required secret: {}", path.display()),
+            });
+        }
+        if missing.is_empty() {
+            return Ok(());
+        }
+        if self.options.strict_secrets {
+            anyhow::bail!("{} required secret(s) missing", missing.len());
+        }
+        self.emitter
+            .message("Create them if you need to store sensitive information");
+        Ok(())
+    }
+
+    pub fn get_target_path(&self, topic: &str, file_name: &str) -> PathBuf {
+        self.topic_dir(topic).join(file_name)
+    }
+
+    /// Where a topic's files go: its `--topic-target`, else the built-in
+    /// location for nvim and zellij, else the home directory itself.
+    fn topic_dir(&self, topic: &str) -> PathBuf {
+        if let Some(dir) = self.options.topic_targets.get(topic) {
+            return self.home_dir.join(dir.strip_prefix("~/").unwrap_or(dir));
+        }
+        match topic {
+            "zellij" => self.home_dir.join(".config").join("zellij"),
+            "nvim" => self.home_dir.join(".config").join("nvim"),
+            _ => self.home_dir.clone(),
+        }
+    }
+
+    pub fn list_configs(&self) -> Result<()> {
+        println!("{}", "Current Configuration Files:".green().bold());
+        println!("{}", "=========================".green());
+
+        let config = self.config()?;
+
+        for mapping in &config.files {
+            let source = self.source_path(mapping)?;
+
+            println!("\n{}:", mapping.source.blue().bold());
+            println!("  Source: {}", self.display_source(&source));
+            for target in mapping.targets() {
+                let target = self.target_path(mapping, target)?;
+                println!("  Target: {}", self.display_target(&target));
+                println!(
+                    "  Status: {}",
+                    if !self.is_enabled(mapping) {
+                        "Disabled".dimmed()
+                    } else if target.exists() {
+                        "Installed".green()
+                    } else {
+                        "Not installed".yellow()
+                    }
+                );
+            }
+            println!("  {}", "-".repeat(50));
+        }
+        Ok(())
+    }
+
+    /// Prints each topic with how many files its mappings cover and how
+    /// many of those are installed.
+    pub fn list_topics(&self) -> Result<()> {
+        let config = self.config()?;
+        let mut topics: BTreeMap<&str, (usize, usize)> = BTreeMap::new();
+
+        for mapping in &config.files {
+            let (files, installed) = topics.entry(mapping.topic()).or_default();
+            if self.is_enabled(mapping) {
+                for (_, _, status) in self.classify_mapping(mapping)? {
+                    *files += 1;
+                    *installed += usize::from(status.is_installed());
+                }
+            } else {
+                *files += self.resolve_files(mapping)?.len();
+            }
+        }
+
+        for (topic, (files, installed)) in topics {
+            let counts = format!("{} file(s), {} installed", files, installed);
+            println!(
+                "{:<20} {}",
+                topic.blue().bold(),
+                if installed == files {
+                    counts.green()
+                } else {
+                    counts.yellow()
+                }
+            );
+        }
+        Ok(())
+    }
+
+    /// Prints the status of every mapping. With `deep`, targets linked as a
+    /// whole directory are also checked file by file against the repo.
+    pub fn check_status(&self, deep: bool) -> Result<()> {
+        println!("{}", "Configuration Status:".green().bold());
+        println!("{}", "===================".green());
+
+        let config = self.config()?;
+        let mut all_good = true;
+        let mut any_adoptable = false;
+        // (installed, missing, drifted, broken)
+        let mut counts = (0, 0, 0, 0);
+
+        for mapping in &config.files {
+            if let Some(reason) = self.skip_reason(mapping) {
+                println!("{} is {}", mapping.source, reason);
+                continue;
+            }
+            let source = self.source_path(mapping)?;
+            if source.is_dir() && is_empty_dir(&source)? {
+                println!("{} is empty: nothing to install", mapping.source);
+                all_good = false;
+                continue;
+            }
+            for (source, target, status) in self.classify_mapping(mapping)? {
+                let discrepancies = if deep && status == TargetStatus::Linked && source.is_dir() {
+                    self.directory_discrepancies(&source)?
+                } else {
+                    Vec::new()
+                };
+                if !discrepancies.is_empty() {
+                    println!(
+                        "{} is linked as a directory, but: {}",
+                        mapping.source,
+                        self.display_target(&target)
+                    );
+                    for discrepancy in &discrepancies {
+                        println!("  {}", discrepancy);
+                    }
+                    counts.2 += 1;
+                    all_good = false;
+                    continue;
+                }
+                match status {
+                    _ if status.is_installed() => counts.0 += 1,
+                    TargetStatus::Missing => counts.1 += 1,
+                    TargetStatus::Broken => counts.3 += 1,
+                    _ => counts.2 += 1,
+                }
+                if !status.is_installed() || status == TargetStatus::Present {
+                    println!(
+                        "{} is {}: {}",
+                        mapping.source,
+                        status.label(),
+                        self.display_target(&target)
+                    );
+                }
+                if let Some(cycle) = cycle_above(&target) {
+                    println!("  symlink cycle: {}", self.describe_cycle(&cycle));
+                }
+                if !status.is_installed() {
+                    all_good = false;
+                    any_adoptable |= status == TargetStatus::Adoptable;
+                }
+            }
+        }
+
+        for path in self.missing_secrets()? {
+            println!("Required secret is missing: {}", self.display_target(&path));
+            all_good = false;
+        }
+
+        for (source, original) in self.unmapped_additions()? {
+            println!(
+                "{} is not mapped (added from {})",
+                self.display_source(&source),
+                self.display_target(&original)
+            );
+            all_good = false;
+        }
+
+        if all_good {
+            println!("\n{}", "All configurations are installed!".green());
+        } else if any_adoptable {
+            println!(
+                "\n{}",
+                "Adoptable targets already match the repo; run `dotfiles adopt` to link them"
+                    .yellow()
+            );
+        }
+        println!(
+            "\n{} installed, {} missing, {} drifted, {} broken",
+            counts.0, counts.1, counts.2, counts.3
+        );
+
+        Ok(())
+    }
+
+    /// Lists sources modified since the last install, which a reinstall
+    /// would pick up, and targets that no longer match their source.
+    pub fn changes_since_install(&self) -> Result<()> {
+        let installed_at = self
+            .state
+            .borrow()
+            .installed_at
+            .clone()
+            .context("No install has been recorded yet")?;
+        let since = chrono::DateTime::parse_from_rfc3339(&installed_at)
+            .context("Failed to parse the last install time in the state file")?;
+        println!("Last install: {}", since.format("%Y-%m-%d %H:%M:%S"));
+
+        let config = self.config()?;
+        let mut modified = Vec::new();
+        let mut drifted = Vec::new();
+        for mapping in config.files.iter().filter(|m| self.is_enabled(m)) {
+            for (source, target, status) in self.classify_mapping(mapping)? {
+                let changed = fs::metadata(&source)
+                    .and_then(|metadata| metadata.modified())
+                    .is_ok_and(|modified| {
+                        chrono::DateTime::<chrono::Local>::from(modified) > since
+                    });
+                if changed && !modified.contains(&source) {
+                    modified.push(source);
+                }
+                if !status.is_installed() {
+                    drifted.push((target, status));
+                }
+            }
+        }
+
+        if modified.is_empty() && drifted.is_empty() {
+            println!("\n{}", "Nothing changed since the last install".green());
+            return Ok(());
+        }
+        if !modified.is_empty() {
+            println!("\n{}", "Modified since install:".yellow().bold());
+            for source in &modified {
+                println!("  {}", self.display_source(source));
+            }
+        }
+        if !drifted.is_empty() {
+            println!("\n{}", "Drifted targets:".yellow().bold());
+            for (target, status) in &drifted {
+                println!("  {} ({})", self.display_target(target), status.label());
+            }
+        }
+        Ok(())
+    }
+
+    /// Lists every (source, target) file pair a mapping covers, walking
+    /// directory sources the same way install does.
+    pub fn resolve_files(&self, mapping: &FileMapping) -> Result<Vec<(PathBuf, PathBuf)>> {
+        let source = self.source_path(mapping)?;
+
+        let mut files = Vec::new();
+        for target in mapping.targets() {
+            let target = self.target_path(mapping, target)?;
+            if mapping.is_glob() {
+                files.extend(self.glob_files(mapping, &target)?);
+            } else if source.is_dir() && !mapping.concat {
+                let settings = self.settings_for(Some(mapping));
+                let mut found = Vec::new();
+                collect_files(&source, &target, settings.max_depth, &mut found)?;
+                // Copy mappings skip directories past max_depth
+                if settings.strategy == Strategy::Copy {
+                    found.retain(|(source, _)| !source.is_dir());
+                }
+                for (file, dest) in &mut found {
+                    if let (Some(name), Some(dir)) = (file.file_name(), dest.parent()) {
+                        if file.is_file() {
+                            *dest = settings.file_target(dir, name);
+                        }
+                    }
+                }
+                files.extend(found);
+            } else {
+                files.push((source.clone(), target));
+            }
+        }
+        Ok(files)
+    }
+
+    /// Classifies every file a mapping covers. Templated and concatenated
+    /// mappings are compared against their output rather than expected to be
+    /// links.
+    pub fn classify_mapping(
+        &self,
+        mapping: &FileMapping,
+    ) -> Result<Vec<(PathBuf, PathBuf, TargetStatus)>> {
+        let files = self.resolve_files(mapping)?;
+        let generated = self.generated_output(mapping)?;
+        let copy = mapping.strategy() == Strategy::Copy;
+        Ok(files
+            .into_iter()
+            .map(|(source, target)| {
+                let status = if let Some(contents) = &generated {
+                    classify_copy(contents, &target)
+                } else if copy {
+                    match fs::read(&source) {
+                        Ok(contents) => classify_copy(&contents, &target),
+                        Err(_) => TargetStatus::Conflict,
+                    }
+                } else {
+                    classify(&source, &target)
+                };
+                let status = seeded(mapping, status);
+                (source, target, status)
+            })
+            .collect())
+    }
+
+    /// Counts enabled targets by status. Unless `deep` is set, file contents
+    /// are never read so this stays cheap enough for a shell prompt.
+    pub fn status_summary(&self, deep: bool) -> Result<StatusSummary> {
+        let config = self.config()?;
+        let mut summary = StatusSummary::default();
+
+        for mapping in config.files.iter().filter(|m| self.is_enabled(m)) {
+            if deep {
+                for (_, _, status) in self.classify_mapping(mapping)? {
+                    summary.add(status);
+                }
+            } else {
+                for (source, target) in self.resolve_files(mapping)? {
+                    let status = classify_fast(
+                        &source,
+                        &target,
+                        mapping.template || mapping.concat || mapping.strategy() == Strategy::Copy,
+                    );
+                    summary.add(seeded(mapping, status));
+                }
+            }
+        }
+
+        Ok(summary)
+    }
+
+    /// Replaces targets that are byte-identical copies of their source with
+    /// links. Nothing is backed up since the content already lives in the repo.
+    pub fn adopt(&self) -> Result<()> {
+        println!("{}", "Adopting existing files...".green());
+
+        let config = self.config()?;
+        let mut adopted = 0;
+
+        for mapping in config.files.iter().filter(|m| self.is_enabled(m)) {
+            for (source, target, status) in self.classify_mapping(mapping)? {
+                match status {
+                    TargetStatus::Adoptable => {
+                        fs::remove_file(&target).context("Failed to remove adopted file")?;
+                        self.link_to(&source, &target)?;
+                        adopted += 1;
+                    }
+                    TargetStatus::Linked | TargetStatus::UpToDate | TargetStatus::Missing => {}
+                    status => println!(
+                        "{} {} ({})",
+                        "Left alone:".yellow(),
+                        target.display(),
+                        status.label()
+                    ),
+                }
+            }
+        }
+
+        self.save_state()?;
+        println!("\nAdopted {} file(s)", adopted);
+        Ok(())
+    }
+
+    pub fn add_config(&self, topic: &str, file: &Path) -> Result<()> {
+        if !file.exists() {
+            anyhow::bail!("File does not exist: {:?}", file);
+        }
+
+        let topic_dir = self.dotfiles_dir.join(topic);
+        fs::create_dir_all(&topic_dir)?;
+
+        let file_name = file.file_name().context("Invalid file name")?;
+        let dest = topic_dir.join(file_name);
+        if dest.exists() {
+            anyhow::bail!(
+                "{} already exists in {}; not overwriting it",
+                file_name.to_string_lossy(),
+                topic
+            );
+        }
+
+        fs::copy(file, &dest)?;
+        let mut meta = TopicMeta::load(&topic_dir)?;
+        meta.files.insert(
+            file_name.to_string_lossy().into_owned(),
+            std::path::absolute(file).context("Failed to resolve file path")?,
+        );
+        meta.save(&topic_dir)?;
+        println!(
+            "Added {} to {} configuration",
+            file_name.to_string_lossy(),
+            topic
+        );
+
+        Ok(())
+    }
+
+    /// Adds every path listed in `list` to `topic`, mapping each to where it
+    /// was found, and writes the config once at the end. Paths are one per
+    /// line; blank lines and `#` comments are skipped, and `~/` means the
+    /// home directory. A path that can't be added is reported and skipped,
+    /// then the command fails once the rest are done.
+    pub fn add_batch(&self, topic: &str, list: &Path) -> Result<()> {
+        let contents = fs::read_to_string(list)
+            .with_context(|| format!("Failed to read {}", list.display()))?;
+        let paths: Vec<PathBuf> = contents
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| match line.strip_prefix("~/") {
+                Some(relative) => self.home_dir.join(relative),
+                None => PathBuf::from(line),
+            })
+            .collect();
+
+        let (config_path, mut doc) = self.editable_config()?;
+        let mut failed = 0;
+        for path in &paths {
+            let added = self.add_batch_entry(&mut doc, topic, path);
+            if let Err(err) = added {
+                println!("{} {}: {:#}", "Failed:".red(), path.display(), err);
+                failed += 1;
+            }
+        }
+        if failed < paths.len() {
+            write_config(&config_path, &doc)?;
+        }
+
+        println!(
+            "Added {} of {} file(s) to {}",
+            paths.len() - failed,
+            paths.len(),
+            topic
+        );
+        if failed > 0 {
+            anyhow::bail!("{} file(s) could not be added", failed);
+        }
+        Ok(())
+    }
+
+    /// Adds one `--batch` path and records its mapping in `doc`, leaving
+    /// `doc` as it was if the file can't be added.
+    fn add_batch_entry(
+        &self,
+        doc: &mut toml_edit::DocumentMut,
+        topic: &str,
+        path: &Path,
+    ) -> Result<()> {
+        let file = std::path::absolute(path).context("Failed to resolve file path")?;
+        let file_name = file
+            .file_name()
+            .context("Invalid file name")?
+            .to_string_lossy()
+            .into_owned();
+        let target = match file.strip_prefix(&self.home_dir) {
+            Ok(relative) => relative.to_string_lossy().into_owned(),
+            Err(_) => file_name.clone(),
+        };
+        let source = format!("{}/{}", topic, file_name);
+
+        config::add_mapping(doc, &source, &target)?;
+        if let Err(err) = self.add_config(topic, &file) {
+            config::remove_mappings(doc, &source, topic, &file_name);
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    /// Files recorded by `add` in a topic's sidecar that no mapping installs,
+    /// with the path each was added from.
+    pub fn unmapped_additions(&self) -> Result<Vec<(PathBuf, PathBuf)>> {
+        let config = self.config()?;
+        let mut mapped = Vec::new();
+        for mapping in &config.files {
+            mapped.extend(
+                self.resolve_files(mapping)?
+                    .into_iter()
+                    .map(|(source, _)| source),
+            );
+        }
+
+        let mut unmapped = Vec::new();
+        let Ok(entries) = fs::read_dir(&self.dotfiles_dir) else {
+            return Ok(unmapped);
+        };
+        for entry in entries {
+            let topic_dir = entry?.path();
+            if !topic_dir.join(TopicMeta::FILE_NAME).is_file() {
+                continue;
+            }
+            for (name, original) in TopicMeta::load(&topic_dir)?.files {
+                let source = topic_dir.join(&name);
+                if source.exists() && !mapped.iter().any(|mapped| source.starts_with(mapped)) {
+                    unmapped.push((source, original));
+                }
+            }
+        }
+        Ok(unmapped)
+    }
+
+    /// Walks through adding `file`: suggests a topic and a target, lets the
+    /// user confirm or edit both, then copies the file into the topic and
+    /// records the mapping in the config.
+    pub fn add_interactive(&self, file: &Path, topic: Option<&str>) -> Result<()> {
+        if !file.exists() {
+            anyhow::bail!("File does not exist: {:?}", file);
+        }
+        let file = std::path::absolute(file).context("Failed to resolve file path")?;
+        let file_name = file
+            .file_name()
+            .context("Invalid file name")?
+            .to_string_lossy()
+            .into_owned();
+
+        let topic: String = dialoguer::Input::new()
+            .with_prompt("Topic")
+            .default(topic.map_or_else(|| suggest_topic(&file, &self.home_dir), str::to_string))
+            .interact_text()?;
+        let target: String = dialoguer::Input::new()
+            .with_prompt("Target (relative to home)")
+            .default(match file.strip_prefix(&self.home_dir) {
+                Ok(relative) => relative.to_string_lossy().into_owned(),
+                Err(_) => file_name.clone(),
+            })
+            .interact_text()?;
+
+        let source = format!("{}/{}", topic, file_name);
+        let confirmed = dialoguer::Confirm::new()
+            .with_prompt(format!("Add {} -> ~/{}?", source, target))
+            .default(true)
+            .interact()?;
+        if !confirmed {
+            println!("Nothing added");
+            return Ok(());
+        }
+
+        let (config_path, mut doc) = self.editable_config()?;
+        config::add_mapping(&mut doc, &source, &target)?;
+
+        self.add_config(&topic, &file)?;
+        write_config(&config_path, &doc)?;
+        println!("Recorded {} -> ~/{} in {:?}", source, target, config_path);
+        Ok(())
+    }
+
+    /// Moves `file` into `topic`, maps it in the config and links it back in
+    /// place, so an existing config comes under management in one step.
+    pub fn add_moved(&self, topic: &str, file: &Path) -> Result<()> {
+        if fs::symlink_metadata(file).is_err() {
+            anyhow::bail!("File does not exist: {:?}", file);
+        }
+        let file = std::path::absolute(file).context("Failed to resolve file path")?;
+        let target = file
+            .strip_prefix(&self.home_dir)
+            .with_context(|| format!("{} is not inside the home directory", file.display()))?
+            .to_string_lossy()
+            .into_owned();
+        let file_name = file.file_name().context("Invalid file name")?;
+        let source = format!("{}/{}", topic, file_name.to_string_lossy());
+
+        for mapping in &self.config()?.files {
+            for mapped in mapping.targets() {
+                if self.target_path(mapping, mapped)? == file {
+                    anyhow::bail!(
+                        "{} is already the target of {}",
+                        file.display(),
+                        mapping.source
+                    );
+                }
+            }
+        }
+        let (config_path, mut doc) = self.editable_config()?;
+        config::add_mapping(&mut doc, &source, &target)?;
+
+        let topic_dir = self.dotfiles_dir.join(topic);
+        let dest = topic_dir.join(file_name);
+        if fs::symlink_metadata(&dest).is_ok() {
+            anyhow::bail!(
+                "{} already exists in {}; not overwriting it",
+                file_name.to_string_lossy(),
+                topic
+            );
+        }
+        fs::create_dir_all(&topic_dir)?;
+        // Across filesystems a rename can't work, so copy instead and keep
+        // the original until the config is written
+        let copied = fs::rename(&file, &dest).is_err();
+        if copied {
+            if let Err(err) = copy_recursive(&file, &dest) {
+                let _ = remove_path(&dest);
+                return Err(err)
+                    .with_context(|| format!("Failed to move {} into the repo", file.display()));
+            }
+        }
+
+        if let Err(err) = write_config(&config_path, &doc) {
+            // Put the file back rather than leave it unmapped in the repo
+            let restored = if copied {
+                remove_path(&dest)
+            } else {
+                fs::rename(&dest, &file)
+            };
+            restored.with_context(|| {
+                format!(
+                    "Failed to move {} back to {}",
+                    dest.display(),
+                    file.display()
+                )
+            })?;
+            return Err(err);
+        }
+        if copied {
+            remove_path(&file)
+                .with_context(|| format!("Failed to remove {} after copying it", file.display()))?;
+        }
+        let mut meta = TopicMeta::load(&topic_dir)?;
+        meta.files
+            .insert(file_name.to_string_lossy().into_owned(), file.clone());
+        meta.save(&topic_dir)?;
+        self.link_to(&dest, &file)?;
+        self.save_state()?;
+        println!("Moved {} into {} and linked it back", file.display(), topic);
+        Ok(())
+    }
+
+    /// Takes `file` out of `topic`: unlinks targets that point at it, drops
+    /// its mappings from the config and deletes it from the repo. Unless
+    /// `keep_empty` is set, a topic directory left without files is removed
+    /// too, along with `[topics.*]` tables left without mappings. With
+    /// `keep_target`, each linked target is replaced by a copy of the file
+    /// instead of being unlinked, so it stays in place unmanaged.
+    pub fn remove_config(
+        &self,
+        topic: &str,
+        file: &str,
+        keep_empty: bool,
+        keep_target: bool,
+    ) -> Result<()> {
+        let topic_dir = self.dotfiles_dir.join(topic);
+        let path = topic_dir.join(file);
+        if fs::symlink_metadata(&path).is_err() {
+            anyhow::bail!("{} is not in {}", file, topic);
+        }
+        let source = format!("{}/{}", topic, file.trim_end_matches('/'));
+        // Edit the config before touching any targets, so a config that
+        // can't be edited leaves everything linked
+        let (config_path, mut doc) = self.editable_config()?;
+        let removed = config::remove_mappings(&mut doc, &source, topic, file);
+        if !keep_empty {
+            config::prune_empty_topics(&mut doc);
+        }
+
+        for mapping in &self.config()?.files {
+            for (mapped, target) in self.resolve_files(mapping)? {
+                if !mapped.starts_with(&path) || !links_to(&target, &mapped) {
+                    continue;
+                }
+                if keep_target && mapped.is_file() {
+                    // Renamed over the link, so the target never goes missing
+                    write_atomic(&target, &fs::read(&mapped)?)?;
+                    fs::set_permissions(&target, fs::metadata(&mapped)?.permissions())?;
+                    self.chown(&target)?;
+                } else if keep_target {
+                    replace_link_with_copy(&mapped, &target)?;
+                    self.chown(&target)?;
+                } else {
+                    fs::remove_file(&target)
+                        .with_context(|| format!("Failed to remove {}", target.display()))?;
+                }
+                self.state
+                    .borrow_mut()
+                    .links
+                    .retain(|link| link.target != target);
+                if keep_target {
+                    println!(
+                        "{} {}",
+                        "Kept as a copy:".green(),
+                        self.display_target(&target)
+                    );
+                } else {
+                    println!("{} {}", "Unlinked:".yellow(), self.display_target(&target));
+                }
+            }
+        }
+        write_config(&config_path, &doc)?;
+
+        if path.is_dir() {
+            fs::remove_dir_all(&path)
+        } else {
+            fs::remove_file(&path)
+        }
+        .with_context(|| format!("Failed to remove {}", path.display()))?;
+        let mut meta = TopicMeta::load(&topic_dir)?;
+        if meta.files.remove(file).is_some() {
+            meta.save(&topic_dir)?;
+        }
+        self.save_state()?;
+        println!(
+            "Removed {} from {} ({} mapping(s) dropped)",
+            file, topic, removed
+        );
+
+        let only_meta = fs::read_dir(&topic_dir)?
+            .map(|entry| entry.map(|entry| entry.file_name()))
+            .collect::<Result<Vec<_>, _>>()?
+            .iter()
+            .all(|name| name == TopicMeta::FILE_NAME);
+        if !keep_empty && only_meta {
+            fs::remove_dir_all(&topic_dir)
+                .with_context(|| format!("Failed to remove {}", topic_dir.display()))?;
+            println!("Removed empty topic {}", topic);
+        }
+        Ok(())
+    }
+
+    /// Enables or disables the mapping with the given source in the config file.
+    pub fn set_enabled(&self, source: &str, enabled: bool) -> Result<()> {
+        let (config_path, mut doc) = self.editable_config()?;
+
+        config::set_mapping_enabled(&mut doc, source, enabled)?;
+        write_config(&config_path, &doc)?;
+
+        println!(
+            "{} {}",
+            if enabled { "Enabled" } else { "Disabled" },
+            source
+        );
+        Ok(())
+    }
+
+    /// Removes directories install created that are now empty, deepest
+    /// first so emptied parents go too. Directories holding anything,
+    /// managed or not, are kept.
+    pub fn gc(&self, dry_run: bool) -> Result<()> {
+        let mut dirs = self.state.borrow().created_dirs.clone();
+        dirs.sort_by_key(|dir| std::cmp::Reverse(dir.components().count()));
+
+        let mut removed = Vec::new();
+        for dir in &dirs {
+            let empty = match fs::read_dir(dir) {
+                // Entries removed earlier in a dry run would be gone by now
+                Ok(mut entries) => entries.all(|entry| {
+                    entry.is_ok_and(|entry| dry_run && removed.contains(&entry.path()))
+                }),
+                Err(_) => continue,
+            };
+            if !empty {
+                continue;
+            }
+            if dry_run {
+                println!("{} {}", "Would remove:".cyan(), dir.display());
+            } else {
+                fs::remove_dir(dir)
+                    .with_context(|| format!("Failed to remove {}", dir.display()))?;
+                println!("{} {}", "Removed:".yellow(), dir.display());
+            }
+            removed.push(dir.clone());
+        }
+
+        if !dry_run {
+            // Forget directories that are gone, whoever removed them
+            self.state
+                .borrow_mut()
+                .created_dirs
+                .retain(|dir| dir.is_dir());
+            self.save_state()?;
+        }
+        println!(
+            "{} {} empty director{}",
+            if dry_run { "Would remove" } else { "Removed" },
+            removed.len(),
+            if removed.len() == 1 { "y" } else { "ies" }
+        );
+        Ok(())
+    }
+
+    pub fn verify(&self) -> Result<()> {
+        println!("{}", "Verifying installation:".green().bold());
+        println!("{}", "======================".green());
+
+        let state = State::load(&self.state_path())?;
+
+        let mut broken_links = Vec::new();
+        let mut missing_sources = Vec::new();
+        for link in &state.links {
+            if !links_to(&link.target, &link.source) {
+                broken_links.push(link);
+            }
+            if !link.source.exists() {
+                missing_sources.push(link);
+            }
+        }
+
+        let mut missing_backups = Vec::new();
+        if self.backup_root.is_dir() {
+            for entry in fs::read_dir(&self.backup_root)? {
+                let manifest_path = entry?.path().join("manifest.toml");
+                if !manifest_path.is_file() {
+                    continue;
+                }
+                let manifest = Manifest::load(&manifest_path)?;
+                missing_backups.extend(
+                    manifest
+                        .backups
+                        .into_iter()
+                        .filter(|record| fs::symlink_metadata(&record.backup).is_err()),
+                );
+            }
+        }
+
+        println!("\n{}", "Links:".blue().bold());
+        if broken_links.is_empty() {
+            println!("  {}", "All recorded links point to their sources".green());
+        }
+        for link in &broken_links {
+            println!(
+                "  {} {} no longer points to {}",
+                "Broken:".red(),
+                link.target.display(),
+                link.source.display()
+            );
+        }
+
+        println!("\n{}", "Backups:".blue().bold());
+        if missing_backups.is_empty() {
+            println!("  {}", "All recorded backups are present".green());
+        }
+        for record in &missing_backups {
+            println!(
+                "  {} {} (backup of {})",
+                "Missing:".red(),
+                record.backup.display(),
+                record.original.display()
+            );
+        }
+
+        println!("\n{}", "Sources:".blue().bold());
+        if missing_sources.is_empty() {
+            println!("  {}", "All linked sources exist".green());
+        }
+        for link in &missing_sources {
+            println!("  {} {}", "Missing:".red(), link.source.display());
+        }
+
+        let problems = broken_links.len() + missing_backups.len() + missing_sources.len();
+        if problems > 0 {
+            anyhow::bail!("Verification found {} problem(s)", problems);
+        }
+
+        println!("\n{}", "Installation verified!".green());
+        Ok(())
+    }
+}
+
+/// Every file under `src_dir` paired with its target under `target_dir`.
+/// Directories more than `max_depth` levels down are listed as a single
+/// pair instead of being walked.
+fn collect_files(
+    src_dir: &Path,
+    target_dir: &Path,
+    max_depth: Option<usize>,
+    files: &mut Vec<(PathBuf, PathBuf)>,
+) -> Result<()> {
+    let filter = DirFilter::load(src_dir)?;
+    for entry in fs::read_dir(src_dir)? {
+        let entry = entry?;
+        if entry.file_name() == TopicMeta::FILE_NAME
+            || !filter.allows(&entry.file_name().to_string_lossy())
+        {
+            continue;
+        }
+        let path = entry.path();
+        let dest = target_dir.join(path.file_name().unwrap());
+
+        if path.is_file() {
+            files.push((path, dest));
+        } else if path.is_dir() && !loops_back(src_dir, &path) {
+            match max_depth {
+                Some(0) => files.push((path, dest)),
+                _ => collect_files(&path, &dest, max_depth.map(|max| max - 1), files)?,
+            }
+        }
+    }
+    Ok(())
+}
+
+/// `path` in single quotes, safe to paste into a POSIX shell command.
+fn shell_quote(path: &Path) -> String {
+    format!("'{}'", path.to_string_lossy().replace('\'', "'\\''"))
+}
+
+/// The symlink cycle `path` or one of its ancestors leads into, if any.
+fn cycle_above(path: &Path) -> Option<Vec<PathBuf>> {
+    path.ancestors().find_map(symlink_cycle)
+}
+
+/// Anything at a `skip_if_exists` target counts as present rather than
+/// drifted, since install leaves it alone.
+fn seeded(mapping: &FileMapping, status: TargetStatus) -> TargetStatus {
+    if mapping.skip_if_exists && !status.is_installed() && status != TargetStatus::Missing {
+        TargetStatus::Present
+    } else {
+        status
+    }
+}
+
+/// A local user, as listed in `/etc/passwd`.
+struct UserAccount {
+    name: String,
+    uid: u32,
+    gid: u32,
+    home: PathBuf,
+}
+
+impl UserAccount {
+    fn lookup(name: &str) -> Result<Self> {
+        if cfg!(not(unix)) {
+            anyhow::bail!("--user is only supported on Unix");
+        }
+        let passwd = fs::read_to_string("/etc/passwd").context("Failed to read /etc/passwd")?;
+        for line in passwd.lines() {
+            // name:password:uid:gid:gecos:home:shell
+            let fields: Vec<&str> = line.split(':').collect();
+            if fields.len() < 7 || fields[0] != name {
+                continue;
+            }
+            return Ok(Self {
+                name: name.to_string(),
+                uid: fields[2].parse().context("Invalid uid in /etc/passwd")?,
+                gid: fields[3].parse().context("Invalid gid in /etc/passwd")?,
+                home: PathBuf::from(fields[5]),
+            });
+        }
+        anyhow::bail!("No such user: {}", name)
+    }
+}
+
+/// Whether we run with root's privileges, which changing owners needs.
+fn is_root() -> bool {
+    #[cfg(unix)]
+    {
+        // SAFETY: geteuid has no preconditions and can't fail
+        unsafe { libc::geteuid() == 0 }
+    }
+    #[cfg(not(unix))]
+    {
+        false
+    }
+}
+
+/// The home directory named by `$HOME` (`%USERPROFILE%` on Windows), with
+/// the variable it came from. Unset, empty and relative values are ignored so
+/// the caller can fall back to the system's idea of home.
+fn home_from_env() -> Option<(&'static str, PathBuf)> {
+    let var = if cfg!(windows) { "USERPROFILE" } else { "HOME" };
+    let home = PathBuf::from(env::var_os(var)?);
+    home.is_absolute().then_some((var, home))
+}
+
+/// Saves an edited config. The new contents go to a synced temp file that is
+/// renamed over the original, so a failed write leaves the old config intact.
+/// The original's permissions are kept.
+fn write_config(path: &Path, doc: &toml_edit::DocumentMut) -> Result<()> {
+    write_atomic_with_mode(path, doc.to_string().as_bytes(), file_mode(path))
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// Whether a source directory holds no files to install, even in
+/// subdirectories.
+/// Removes a file, link or whole directory without following links.
+fn remove_path(path: &Path) -> std::io::Result<()> {
+    if fs::symlink_metadata(path)?.is_dir() {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_file(path)
+    }
+}
+
+fn is_empty_dir(dir: &Path) -> Result<bool> {
+    let mut files = Vec::new();
+    collect_files(dir, dir, None, &mut files)?;
+    Ok(files.is_empty())
+}
+
+/// The permission bits of `path`, where the platform has them.
+fn file_mode(path: &Path) -> Option<u32> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::metadata(path)
+            .ok()
+            .map(|metadata| metadata.permissions().mode() & 0o7777)
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = path;
+        None
+    }
+}
+
+fn set_mode(path: &Path, mode: u32) -> Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        if file_mode(path) != Some(mode) {
+            fs::set_permissions(path, fs::Permissions::from_mode(mode))
+                .with_context(|| format!("Failed to set permissions on {}", path.display()))?;
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = (path, mode);
+    Ok(())
+}
+
+fn set_readonly(path: &Path) -> Result<()> {
+    let mut permissions = fs::metadata(path)?.permissions();
+    if !permissions.readonly() {
+        permissions.set_readonly(true);
+        fs::set_permissions(path, permissions)
+            .with_context(|| format!("Failed to make {} read-only", path.display()))?;
+    }
+    Ok(())
+}
+
+/// Every file under `dir`, sorted by path, in the order concat joins them.
+fn fragments(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    collect_files(dir, dir, None, &mut files)?;
+    let mut fragments: Vec<PathBuf> = files.into_iter().map(|(source, _)| source).collect();
+    fragments.sort();
+    Ok(fragments)
+}
+
+/// Builds a command that runs `command` through the platform shell.
+fn shell_command(command: &str) -> Command {
+    #[cfg(unix)]
+    {
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c").arg(command);
+        cmd
+    }
+    #[cfg(windows)]
+    {
+        let mut cmd = Command::new("cmd");
+        cmd.arg("/C").arg(command);
+        cmd
+    }
+}
+
+/// Guesses a topic for `file`: the app directory for files under
+/// `~/.config/<app>/`, otherwise the file name without a leading dot or
+/// extension (`~/.zshrc` becomes `zshrc`).
+fn suggest_topic(file: &Path, home_dir: &Path) -> String {
+    if let Ok(relative) = file.strip_prefix(home_dir.join(".config")) {
+        let mut components = relative.components();
+        if let (Some(app), Some(_)) = (components.next(), components.next()) {
+            return app.as_os_str().to_string_lossy().into_owned();
+        }
+    }
+
+    let name = file
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let name = name.trim_start_matches('.');
+    match name.split_once('.') {
+        Some((stem, _)) if !stem.is_empty() => stem.to_string(),
+        _ => name.to_string(),
+    }
+}
+
+/// The login name: `$USER`, falling back to `whoami`.
+fn current_user() -> Option<String> {
+    env::var("USER")
+        .ok()
+        .filter(|user| !user.is_empty())
+        .or_else(|| {
+            let output = Command::new("whoami").output().ok()?;
+            let user = String::from_utf8(output.stdout).ok()?.trim().to_string();
+            (output.status.success() && !user.is_empty()).then_some(user)
+        })
+}
+
+/// Whether joining `path` onto a directory could leave it: absolute paths,
+/// or `..` components that climb above the start.
+fn escapes_root(path: &Path) -> bool {
+    let mut depth = 0usize;
+    for component in path.components() {
+        match component {
+            Component::Normal(_) => depth += 1,
+            Component::CurDir => {}
+            Component::ParentDir => match depth.checked_sub(1) {
+                Some(parent) => depth = parent,
+                None => return true,
+            },
+            Component::RootDir | Component::Prefix(_) => return true,
+        }
+    }
+    false
+}
+
+/// Whether `path` is `dir` or somewhere beneath it, also following symlinks
+/// in whatever part of `path` already exists.
+fn is_within(path: &Path, dir: &Path) -> bool {
+    if path.starts_with(dir) {
+        return true;
+    }
+    let Ok(dir) = fs::canonicalize(dir) else {
+        return false;
+    };
+
+    // Canonicalize the deepest existing ancestor and re-attach the rest
+    let mut existing = path;
+    let mut rest = Vec::new();
+    while fs::symlink_metadata(existing).is_err() {
+        let (Some(parent), Some(name)) = (existing.parent(), existing.file_name()) else {
+            return false;
+        };
+        rest.push(name);
+        existing = parent;
+    }
+    // The target itself may be a link into the repo that install would
+    // replace; only its parent directories decide where it lives
+    if rest.is_empty() {
+        let (Some(parent), Some(name)) = (existing.parent(), existing.file_name()) else {
+            return false;
+        };
+        rest.push(name);
+        existing = parent;
     }
+    let Ok(mut resolved) = fs::canonicalize(existing) else {
+        return false;
+    };
+    resolved.extend(rest.iter().rev());
+    resolved.starts_with(dir)
 }
@@ -0,0 +1,434 @@
+use super::{BackupStrategy, DotfileManager};
+use crate::config::FileMapping;
+use crate::events::Event;
+use crate::fsutil::copy_recursive;
+use crate::state::Manifest;
+use anyhow::{Context, Result};
+use colored::*;
+use std::{
+    ffi::OsStr,
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+impl DotfileManager {
+    /// Copies every existing target of `mappings` into a fresh backup
+    /// directory and writes its manifest, without linking anything. The
+    /// result can be brought back with [`DotfileManager::restore`].
+    pub fn backup_only(&self, mappings: &[&FileMapping]) -> Result<()> {
+        let mut backed_up = 0;
+        for mapping in mappings.iter().filter(|mapping| self.is_enabled(mapping)) {
+            for target in mapping.targets() {
+                let target = self.target_path(mapping, target)?;
+                if fs::symlink_metadata(&target).is_err() {
+                    continue;
+                }
+                if self.options.dry_run {
+                    self.emitter.emit(&Event::Planned {
+                        action: "back up",
+                        source: None,
+                        target: &target,
+                    });
+                } else {
+                    self.save_backup(&target, BackupStrategy::Copy)?;
+                }
+                backed_up += 1;
+            }
+        }
+
+        if backed_up > 0 && !self.options.dry_run {
+            self.emitter.message(format!(
+                "Backed up {} target(s) to {}",
+                backed_up,
+                self.backup_dir.display()
+            ));
+        } else if backed_up == 0 {
+            self.emitter.message("No existing targets to back up");
+        }
+        Ok(())
+    }
+
+    /// Puts back every file recorded in a backup's manifest: the named
+    /// backup directory, or the most recent one. Whatever is at an original
+    /// path now is itself backed up first unless it is a symlink.
+    /// Archived backups are extracted for the restore and removed again
+    /// afterwards.
+    pub fn restore(&self, name: Option<&str>) -> Result<()> {
+        let backup_dir = match name {
+            Some(name) => self
+                .backup_root
+                .join(name.trim_end_matches(ARCHIVE_EXTENSION)),
+            None => self
+                .latest_backup()?
+                .context("No backups with a manifest found")?,
+        };
+        let archive = archive_path(&backup_dir);
+        if !backup_dir.exists() && archive.is_file() {
+            println!("{} {}", "Extracting".green(), archive.display());
+            tar(&[
+                "-xzf".as_ref(),
+                archive.as_os_str(),
+                "-C".as_ref(),
+                self.backup_root.as_os_str(),
+            ])?;
+            let restored = self.restore_from(&backup_dir);
+            fs::remove_dir_all(&backup_dir)
+                .with_context(|| format!("Failed to remove {}", backup_dir.display()))?;
+            return restored;
+        }
+        self.restore_from(&backup_dir)
+    }
+
+    fn restore_from(&self, backup_dir: &Path) -> Result<()> {
+        // Files currently in the way are moved into this run's backup
+        // directory, which must not be the one being restored
+        if backup_dir == self.backup_dir {
+            anyhow::bail!(
+                "Backup {} was just created; wait a second before restoring it",
+                backup_dir.display()
+            );
+        }
+        let manifest_path = backup_dir.join("manifest.toml");
+        let manifest = Manifest::load(&manifest_path)
+            .with_context(|| format!("No usable manifest in {}", backup_dir.display()))?;
+
+        println!("{} {}", "Restoring from".green(), backup_dir.display());
+        for record in &manifest.backups {
+            // A backed-up symlink may dangle, so look at the link itself
+            if fs::symlink_metadata(&record.backup).is_err() {
+                println!(
+                    "{} {} (backup is missing)",
+                    "Skipped:".yellow(),
+                    record.original.display()
+                );
+                continue;
+            }
+
+            if let Ok(metadata) = fs::symlink_metadata(&record.original) {
+                if metadata.file_type().is_symlink() {
+                    fs::remove_file(&record.original).context("Failed to remove symlink")?;
+                } else {
+                    self.save_backup(&record.original, BackupStrategy::Move)?;
+                }
+            }
+            copy_recursive(&record.backup, &record.original)
+                .with_context(|| format!("Failed to restore {}", record.original.display()))?;
+            self.state
+                .borrow_mut()
+                .links
+                .retain(|link| link.target != record.original);
+            println!("{} {}", "Restored:".green(), record.original.display());
+        }
+
+        self.save_state()
+    }
+
+    /// The newest backup directory that has a manifest, or failing that the
+    /// newest archived one.
+    fn latest_backup(&self) -> Result<Option<PathBuf>> {
+        let Ok(entries) = fs::read_dir(&self.backup_root) else {
+            return Ok(None);
+        };
+        let mut dirs = Vec::new();
+        for entry in entries {
+            let path = entry?.path();
+            if path.join("manifest.toml").is_file() {
+                dirs.push(path);
+            }
+        }
+        // Directory names are timestamps, so they sort chronologically
+        dirs.sort();
+        if dirs.is_empty() {
+            // Fall back to the newest archive, named as the directory it holds
+            return Ok(self.archives()?.pop().map(|archive| {
+                PathBuf::from(
+                    archive
+                        .to_string_lossy()
+                        .trim_end_matches(ARCHIVE_EXTENSION),
+                )
+            }));
+        }
+        Ok(dirs.pop())
+    }
+
+    /// Compresses each backup run older than `older_than` into a `.tar.gz`
+    /// beside it and removes the directory, keeping it restorable at a
+    /// fraction of the space and inodes.
+    pub fn archive_backups(&self, older_than: chrono::Duration, dry_run: bool) -> Result<()> {
+        let cutoff = chrono::Local::now().naive_local() - older_than;
+        let mut runs = self.backup_runs()?;
+        runs.sort_by_key(|(_, taken)| *taken);
+
+        let (mut archived, mut saved) = (0, 0);
+        for (dir, taken) in runs {
+            if taken >= cutoff || dir == self.backup_dir {
+                continue;
+            }
+            let size = dir_size(&dir)?;
+            let archive = archive_path(&dir);
+            if dry_run {
+                println!(
+                    "{} {} ({})",
+                    "Would archive:".cyan(),
+                    dir.display(),
+                    format_size(size)
+                );
+                archived += 1;
+                continue;
+            }
+
+            let name = dir.file_name().context("Invalid backup directory")?;
+            tar(&[
+                "-czf".as_ref(),
+                archive.as_os_str(),
+                "-C".as_ref(),
+                self.backup_root.as_os_str(),
+                name,
+            ])?;
+            fs::remove_dir_all(&dir)
+                .with_context(|| format!("Failed to remove {}", dir.display()))?;
+            let compressed = fs::metadata(&archive)?.len();
+            println!(
+                "{} {} ({} -> {})",
+                "Archived:".green(),
+                dir.display(),
+                format_size(size),
+                format_size(compressed)
+            );
+            archived += 1;
+            saved += size.saturating_sub(compressed);
+        }
+
+        if dry_run {
+            println!("Would archive {} backup(s)", archived);
+        } else {
+            println!(
+                "Archived {} backup(s), {} saved",
+                archived,
+                format_size(saved)
+            );
+        }
+        Ok(())
+    }
+
+    /// Deletes old backup runs, archived or not. A run is removed only if it
+    /// falls outside the `keep` newest and is older than `older_than`, for
+    /// whichever of the two are given. With `dry_run`, only reports what
+    /// would go and how much space that frees.
+    pub fn clean(
+        &self,
+        keep: Option<usize>,
+        older_than: Option<chrono::Duration>,
+        dry_run: bool,
+    ) -> Result<()> {
+        if keep.is_none() && older_than.is_none() {
+            anyhow::bail!("Pass --keep and/or --older-than to choose what to remove");
+        }
+
+        let mut runs = self.backup_runs()?;
+        for archive in self.archives()? {
+            if let Some(taken) = archive_taken(&archive) {
+                runs.push((archive, taken));
+            }
+        }
+        // Newest first, so the first `keep` are the ones retained
+        runs.sort_by_key(|(_, taken)| std::cmp::Reverse(*taken));
+        let cutoff = older_than.map(|age| chrono::Local::now().naive_local() - age);
+
+        let mut removed = 0;
+        let mut freed = 0;
+        for (index, (run, taken)) in runs.iter().enumerate() {
+            let beyond_keep = keep.is_none_or(|keep| index >= keep);
+            let too_old = cutoff.is_none_or(|cutoff| *taken < cutoff);
+            if !(beyond_keep && too_old) {
+                continue;
+            }
+
+            let size = dir_size(run)?;
+            if dry_run {
+                println!(
+                    "{} {} ({})",
+                    "Would remove:".cyan(),
+                    run.display(),
+                    format_size(size)
+                );
+            } else {
+                if run.is_dir() {
+                    fs::remove_dir_all(run)
+                } else {
+                    fs::remove_file(run)
+                }
+                .with_context(|| format!("Failed to remove {}", run.display()))?;
+                println!(
+                    "{} {} ({})",
+                    "Removed:".yellow(),
+                    run.display(),
+                    format_size(size)
+                );
+            }
+            removed += 1;
+            freed += size;
+        }
+
+        println!(
+            "{} {} backup(s), {} {}",
+            if dry_run { "Would remove" } else { "Removed" },
+            removed,
+            format_size(freed),
+            if dry_run { "would be freed" } else { "freed" }
+        );
+        Ok(())
+    }
+
+    /// Lists backup runs, oldest first, with their sizes, then the size of
+    /// the whole backup root (state and audit log included). With
+    /// `total_only`, prints just that total.
+    pub fn list_backups(&self, total_only: bool) -> Result<()> {
+        let total = if self.backup_root.exists() {
+            dir_size(&self.backup_root)?
+        } else {
+            0
+        };
+        let mut runs = self.backup_runs()?;
+        runs.sort_by_key(|(_, taken)| *taken);
+        let archives = self.archives()?;
+        if !total_only {
+            for (dir, _) in &runs {
+                println!("{} ({})", dir.display(), format_size(dir_size(dir)?));
+            }
+            for archive in &archives {
+                println!(
+                    "{} ({}, archived)",
+                    archive.display(),
+                    format_size(fs::metadata(archive)?.len())
+                );
+            }
+        }
+        if total_only {
+            println!("{}", format_size(total));
+        } else {
+            println!(
+                "{} backup(s), {} total",
+                runs.len() + archives.len(),
+                format_size(total)
+            );
+        }
+        Ok(())
+    }
+
+    /// Warns when the backup root has grown past `--backup-warn-size`.
+    pub(super) fn warn_backup_size(&self) -> Result<()> {
+        let Some(limit) = self.options.backup_warn_size else {
+            return Ok(());
+        };
+        let size = dir_size(&self.backup_root)?;
+        if size > limit {
+            self.emitter.emit(&Event::Warning {
+                message: &format!(
+                    "Backups in {} take up {} (more than {}); run `dotfiles clean` to remove old ones",
+                    self.backup_root.display(),
+                    format_size(size),
+                    format_size(limit)
+                ),
+            });
+        }
+        Ok(())
+    }
+
+    /// Backups compressed by `archive-backups`, oldest first.
+    fn archives(&self) -> Result<Vec<PathBuf>> {
+        let mut archives = Vec::new();
+        let Ok(entries) = fs::read_dir(&self.backup_root) else {
+            return Ok(archives);
+        };
+        for entry in entries {
+            let path = entry?.path();
+            if path.to_string_lossy().ends_with(ARCHIVE_EXTENSION) {
+                archives.push(path);
+            }
+        }
+        archives.sort();
+        Ok(archives)
+    }
+
+    /// Every backup run directory with the time it was taken, parsed from its
+    /// name.
+    fn backup_runs(&self) -> Result<Vec<(PathBuf, chrono::NaiveDateTime)>> {
+        let mut runs = Vec::new();
+        let Ok(entries) = fs::read_dir(&self.backup_root) else {
+            return Ok(runs);
+        };
+        for entry in entries {
+            let entry = entry?;
+            if !entry.file_type()?.is_dir() {
+                continue;
+            }
+            let name = entry.file_name();
+            if let Ok(taken) =
+                chrono::NaiveDateTime::parse_from_str(&name.to_string_lossy(), "%Y%m%d_%H%M%S")
+            {
+                runs.push((entry.path(), taken));
+            }
+        }
+        Ok(runs)
+    }
+}
+
+const ARCHIVE_EXTENSION: &str = ".tar.gz";
+
+/// Where `archive-backups` puts the archive of a backup directory.
+fn archive_path(backup_dir: &Path) -> PathBuf {
+    let mut path = backup_dir.as_os_str().to_owned();
+    path.push(ARCHIVE_EXTENSION);
+    PathBuf::from(path)
+}
+
+/// When the run an archive holds was taken, parsed from its name.
+fn archive_taken(archive: &Path) -> Option<chrono::NaiveDateTime> {
+    let name = archive.file_name()?.to_str()?;
+    let stamp = name.strip_suffix(ARCHIVE_EXTENSION)?;
+    chrono::NaiveDateTime::parse_from_str(stamp, "%Y%m%d_%H%M%S").ok()
+}
+
+fn tar(args: &[&OsStr]) -> Result<()> {
+    let output = Command::new("tar")
+        .args(args)
+        .output()
+        .context("Failed to run tar")?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "tar failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(())
+}
+
+/// Total size of the files under `path`, not following symlinks.
+fn dir_size(path: &Path) -> Result<u64> {
+    let metadata = fs::symlink_metadata(path)?;
+    if !metadata.is_dir() {
+        return Ok(metadata.len());
+    }
+    let mut total = 0;
+    for entry in fs::read_dir(path)? {
+        total += dir_size(&entry?.path())?;
+    }
+    Ok(total)
+}
+
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
@@ -0,0 +1,80 @@
+use super::{sync, DotfileManager};
+use crate::filter::DirFilter;
+use crate::state::TopicMeta;
+use anyhow::Result;
+use std::{fs, path::Path};
+
+impl DotfileManager {
+    /// What's wrong with the repo directory behind a directory link, one line
+    /// per file: files git tracks or `add` recorded that are gone, links
+    /// inside it that are broken or lead out of the repo, and entries the
+    /// directory's filter excludes that the link exposes anyway.
+    pub(super) fn directory_discrepancies(&self, source: &Path) -> Result<Vec<String>> {
+        let mut discrepancies = Vec::new();
+
+        // Not being a git repository just means there's no history to check
+        if let Ok(deleted) = sync::git(
+            &self.dotfiles_dir,
+            &["ls-files", "--deleted", "--", &source.to_string_lossy()],
+        ) {
+            for path in deleted.lines().filter(|line| !line.is_empty()) {
+                discrepancies.push(format!("{}: tracked by git but missing", path));
+            }
+        }
+
+        if source.join(TopicMeta::FILE_NAME).is_file() {
+            for name in TopicMeta::load(source)?.files.keys() {
+                if fs::symlink_metadata(source.join(name)).is_err() {
+                    discrepancies.push(format!(
+                        "{}: added with `dotfiles add` but missing",
+                        self.display_source(&source.join(name))
+                    ));
+                }
+            }
+        }
+
+        let repo = fs::canonicalize(&self.dotfiles_dir).unwrap_or(self.dotfiles_dir.clone());
+        self.walk_linked_dir(source, &repo, &mut discrepancies)?;
+        Ok(discrepancies)
+    }
+
+    fn walk_linked_dir(
+        &self,
+        dir: &Path,
+        repo: &Path,
+        discrepancies: &mut Vec<String>,
+    ) -> Result<()> {
+        let filter = DirFilter::load(dir)?;
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let shown = self.display_source(&path);
+            if name != DirFilter::INCLUDE_FILE
+                && name != DirFilter::EXCLUDE_FILE
+                && name != TopicMeta::FILE_NAME
+                && !filter.allows(&name)
+            {
+                discrepancies.push(format!(
+                    "{}: filtered out, but visible through the directory link",
+                    shown
+                ));
+            }
+
+            if entry.file_type()?.is_symlink() {
+                match fs::canonicalize(&path) {
+                    Err(_) => discrepancies.push(format!("{}: broken link", shown)),
+                    Ok(resolved) if !resolved.starts_with(repo) => discrepancies.push(format!(
+                        "{}: leads outside the repo, to {}",
+                        shown,
+                        resolved.display()
+                    )),
+                    Ok(_) => {}
+                }
+            } else if entry.file_type()?.is_dir() {
+                self.walk_linked_dir(&path, repo, discrepancies)?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,84 @@
+use super::DotfileManager;
+use crate::diff::{side_by_side, unified, DiffFormat};
+use crate::status::TargetStatus;
+use anyhow::Result;
+use std::{env, fs};
+
+/// Column count assumed when `$COLUMNS` isn't set.
+const DEFAULT_COLUMNS: usize = 160;
+
+impl DotfileManager {
+    /// Shows how each target differs from what install would put there, for
+    /// every enabled mapping or only those in `topic`. Targets already in
+    /// place, or identical and only waiting to be adopted, are left out.
+    pub fn diff(&self, topic: Option<&str>, format: DiffFormat) -> Result<()> {
+        let config = self.config()?;
+        let columns = env::var("COLUMNS")
+            .ok()
+            .and_then(|columns| columns.parse().ok())
+            .unwrap_or(DEFAULT_COLUMNS);
+        let width = columns.saturating_sub(3) / 2;
+
+        let mut differing = 0;
+        for mapping in config
+            .files
+            .iter()
+            .filter(|mapping| topic.is_none_or(|topic| mapping.topic() == topic))
+            .filter(|mapping| self.is_enabled(mapping))
+        {
+            let generated = self.generated_output(mapping)?;
+            let mut differs = false;
+            for (source, target, status) in self.classify_mapping(mapping)? {
+                if status.is_installed() || status == TargetStatus::Adoptable {
+                    continue;
+                }
+                differs = true;
+                if format == DiffFormat::NameOnly {
+                    continue;
+                }
+
+                let label = self.display_target(&target);
+                let expected = match &generated {
+                    Some(contents) => contents.clone(),
+                    None => fs::read(&source)?,
+                };
+                let (current, old_label) = match status {
+                    TargetStatus::Missing => (Vec::new(), "/dev/null".to_string()),
+                    TargetStatus::Conflict if target.is_file() && !target.is_symlink() => {
+                        (fs::read(&target)?, label.clone())
+                    }
+                    // A link or directory in the way has no content to compare
+                    _ => {
+                        println!("{} ({})\n", label, status.label());
+                        continue;
+                    }
+                };
+                let new_label = source.display().to_string();
+                match (
+                    std::str::from_utf8(&current),
+                    std::str::from_utf8(&expected),
+                ) {
+                    (Ok(old), Ok(new)) => match format {
+                        DiffFormat::SideBySide => {
+                            println!("{} -> {}", old_label, new_label);
+                            println!("{}", side_by_side(old, new, width));
+                        }
+                        _ => println!("{}", unified(old, new, &old_label, &new_label)),
+                    },
+                    _ => println!("Binary files {} and {} differ\n", old_label, new_label),
+                }
+            }
+            if differs {
+                differing += 1;
+                if format == DiffFormat::NameOnly {
+                    println!("{}", mapping.source);
+                }
+            }
+        }
+
+        if differing == 0 && format != DiffFormat::NameOnly {
+            println!("No differences");
+        }
+        Ok(())
+    }
+}
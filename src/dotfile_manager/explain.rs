@@ -0,0 +1,200 @@
+use super::DotfileManager;
+use crate::config::{FileMapping, Strategy};
+use anyhow::Result;
+use colored::*;
+use serde::Serialize;
+use std::{env, path::PathBuf};
+
+/// Every decision install makes about one mapping, from [`DotfileManager::explain`].
+#[derive(Serialize, Debug)]
+pub struct Explanation {
+    pub source: String,
+    pub name: Option<String>,
+    /// Whether install would act on the mapping at all.
+    pub included: bool,
+    pub checks: Vec<Check>,
+    /// Where the source was found, after `source_roots`.
+    pub resolved_source: PathBuf,
+    /// For a glob, whether it matches anything.
+    pub source_exists: bool,
+    /// How targets are put in place: link, copy, template or concat.
+    pub strategy: &'static str,
+    pub targets: Vec<ExplainedTarget>,
+}
+
+/// One filter a mapping has to pass to be installed.
+#[derive(Serialize, Debug)]
+pub struct Check {
+    pub check: &'static str,
+    pub passed: bool,
+    pub detail: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ExplainedTarget {
+    /// The target as written in the config.
+    pub target: String,
+    pub resolved: Option<PathBuf>,
+    /// Why the target doesn't resolve, if it doesn't.
+    pub error: Option<String>,
+}
+
+impl DotfileManager {
+    /// Explains the mappings whose source or name is `source`: the result of
+    /// each filter (all of them are evaluated, even after one fails), where
+    /// the source is found and what each target resolves to. Nothing is
+    /// changed, though a `when` predicate does run.
+    pub fn explain(&self, source: &str) -> Result<Vec<Explanation>> {
+        let config = self.config()?;
+        let wanted = source.trim_end_matches('/');
+        let mappings: Vec<&FileMapping> = config
+            .files
+            .iter()
+            .filter(|mapping| {
+                mapping.source.trim_end_matches('/') == wanted
+                    || mapping.name.as_deref() == Some(wanted)
+            })
+            .collect();
+        if mappings.is_empty() {
+            anyhow::bail!("No mapping with source or name {}", source);
+        }
+
+        let mut explanations = Vec::new();
+        for mapping in mappings {
+            let checks = self.checks(mapping);
+            let resolved_source = self.source_path(mapping)?;
+            explanations.push(Explanation {
+                source: mapping.source.clone(),
+                name: mapping.name.clone(),
+                included: checks.iter().all(|check| check.passed),
+                checks,
+                source_exists: if mapping.is_glob() {
+                    self.resolve_files(mapping)
+                        .is_ok_and(|files| !files.is_empty())
+                } else {
+                    resolved_source.exists()
+                },
+                resolved_source,
+                strategy: if mapping.template {
+                    "template"
+                } else if mapping.concat {
+                    "concat"
+                } else {
+                    match mapping.strategy() {
+                        Strategy::Link => "link",
+                        Strategy::Copy => "copy",
+                    }
+                },
+                targets: mapping
+                    .targets()
+                    .into_iter()
+                    .map(|target| match self.target_path(mapping, target) {
+                        Ok(path) => ExplainedTarget {
+                            target: target.to_string(),
+                            resolved: Some(path),
+                            error: None,
+                        },
+                        Err(err) => ExplainedTarget {
+                            target: target.to_string(),
+                            resolved: None,
+                            error: Some(format!("{:#}", err)),
+                        },
+                    })
+                    .collect(),
+            });
+        }
+        Ok(explanations)
+    }
+
+    /// The same filters as [`DotfileManager::is_enabled`], one by one.
+    fn checks(&self, mapping: &FileMapping) -> Vec<Check> {
+        let os = self.options.os.as_deref().unwrap_or(env::consts::OS);
+        let mut checks = vec![Check {
+            check: "enabled",
+            passed: mapping.enabled.unwrap_or(true),
+            detail: match mapping.enabled {
+                Some(enabled) => format!("enabled = {}", enabled),
+                None => "enabled by default".to_string(),
+            },
+        }];
+
+        checks.push(match &mapping.os {
+            None => Check {
+                check: "os",
+                passed: true,
+                detail: "not limited to any OS".to_string(),
+            },
+            Some(targets) => {
+                let excluded: Vec<&String> = targets
+                    .iter()
+                    .filter(|target| self.options.exclude_os.contains(target))
+                    .collect();
+                let listed = targets.iter().any(|target| target == os);
+                Check {
+                    check: "os",
+                    passed: listed && excluded.is_empty(),
+                    detail: if !excluded.is_empty() {
+                        format!(
+                            "limited to {} and --exclude-os {} is one of them",
+                            targets.join(", "),
+                            excluded[0]
+                        )
+                    } else if listed {
+                        format!("limited to {}, which includes {}", targets.join(", "), os)
+                    } else {
+                        format!("limited to {}, not {}", targets.join(", "), os)
+                    },
+                }
+            }
+        });
+
+        if let Some(when) = &mapping.when {
+            let passed = self.predicate(when);
+            checks.push(Check {
+                check: "when",
+                passed,
+                detail: format!("`{}` {}", when, if passed { "exited 0" } else { "failed" }),
+            });
+        }
+        checks
+    }
+}
+
+impl Explanation {
+    /// Prints the explanation for people rather than scripts.
+    pub fn print(&self) {
+        let verdict = if self.included {
+            "included".green()
+        } else {
+            "excluded".red()
+        };
+        println!("{} ({})", self.source.bold(), verdict);
+        if let Some(name) = &self.name {
+            println!("  name: {}", name);
+        }
+        for check in &self.checks {
+            let mark = if check.passed {
+                "ok".green()
+            } else {
+                "no".red()
+            };
+            println!("  [{}] {}: {}", mark, check.check, check.detail);
+        }
+        println!(
+            "  source: {}{}",
+            self.resolved_source.display(),
+            if self.source_exists { "" } else { " (missing)" }
+        );
+        println!("  strategy: {}", self.strategy);
+        for target in &self.targets {
+            match (&target.resolved, &target.error) {
+                (Some(path), _) => println!("  target: {} -> {}", target.target, path.display()),
+                (None, error) => println!(
+                    "  target: {} ({})",
+                    target.target,
+                    error.as_deref().unwrap_or_default().red()
+                ),
+            }
+        }
+    }
+}
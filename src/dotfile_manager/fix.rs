@@ -0,0 +1,92 @@
+use super::DotfileManager;
+use crate::config::{FileMapping, Strategy};
+use crate::status::TargetStatus;
+use anyhow::{Context, Result};
+use colored::*;
+use std::{fs, path::Path};
+
+impl DotfileManager {
+    /// Walks every target that isn't installed and offers the matching fix,
+    /// applying only the ones approved. With `yes`, applies every safe fix
+    /// (creating missing targets, relinking broken links, adopting identical
+    /// files) without asking and leaves the rest alone.
+    pub fn fix(&self, yes: bool) -> Result<()> {
+        println!("{}", "Fixing configuration status:".green().bold());
+
+        let config = self.config()?;
+        let mut fixed = 0;
+        let mut left = 0;
+
+        for mapping in config.files.iter().filter(|m| self.is_enabled(m)) {
+            for (source, target, status) in self.classify_mapping(mapping)? {
+                let Some((description, safe)) = describe_fix(status) else {
+                    continue;
+                };
+                println!(
+                    "\n{} is {}: {}",
+                    mapping.source,
+                    status.label(),
+                    target.display()
+                );
+
+                let apply = if yes {
+                    safe
+                } else {
+                    dialoguer::Select::new()
+                        .with_prompt("Fix")
+                        .items([description, "Skip"])
+                        .default(0)
+                        .interact()?
+                        == 0
+                };
+                if apply {
+                    self.apply_fix(mapping, &source, &target, status)?;
+                    fixed += 1;
+                } else {
+                    println!("{} {}", "Left as is:".dimmed(), target.display());
+                    left += 1;
+                }
+            }
+        }
+
+        self.save_state()?;
+        println!("\nFixed {} target(s), left {} as is", fixed, left);
+        Ok(())
+    }
+
+    fn apply_fix(
+        &self,
+        mapping: &FileMapping,
+        source: &Path,
+        target: &Path,
+        status: TargetStatus,
+    ) -> Result<()> {
+        let settings = self.settings_for(Some(mapping));
+        if let Some(parent) = target.parent() {
+            self.ensure_target_dir(parent)?;
+        }
+
+        if let Some(contents) = self.generated_output(mapping)? {
+            self.write_output(source, target, &contents, &settings, true)?;
+            return Ok(());
+        }
+        // The content already lives in the repo, so there is nothing to back up
+        if status == TargetStatus::Adoptable && mapping.strategy() == Strategy::Link {
+            fs::remove_file(target).context("Failed to remove adopted file")?;
+        }
+        self.place(source, target, &settings)?;
+        Ok(())
+    }
+}
+
+/// The fix offered for a status, and whether it is safe to apply unasked.
+fn describe_fix(status: TargetStatus) -> Option<(&'static str, bool)> {
+    match status {
+        TargetStatus::Linked | TargetStatus::UpToDate | TargetStatus::Present => None,
+        TargetStatus::Missing => Some(("Install it", true)),
+        TargetStatus::Broken => Some(("Relink the broken link", true)),
+        TargetStatus::Adoptable => Some(("Adopt the identical file", true)),
+        TargetStatus::WrongLink => Some(("Back up the link and relink", false)),
+        TargetStatus::Conflict => Some(("Back up the target and replace it", false)),
+    }
+}
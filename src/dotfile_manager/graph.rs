@@ -0,0 +1,77 @@
+use super::DotfileManager;
+use crate::status::StatusSummary;
+use anyhow::Result;
+use std::{fmt::Write, path::Path};
+
+impl DotfileManager {
+    /// Renders mappings as a Graphviz DOT graph: one node per source, one per
+    /// target, colored by install status.
+    pub fn graph(&self) -> Result<String> {
+        let config = self.config()?;
+        let mut dot = String::from(
+            "digraph dotfiles {\n    rankdir=LR;\n    node [fontname=\"monospace\"];\n\n",
+        );
+
+        for (index, mapping) in config.files.iter().enumerate() {
+            let source_id = format!("source{}", index);
+            writeln!(
+                dot,
+                "    {} [label=\"{}\", shape=box{}];",
+                source_id,
+                escape(&mapping.source),
+                if self.is_enabled(mapping) {
+                    ""
+                } else {
+                    ", style=dashed"
+                }
+            )?;
+
+            let statuses = if self.is_enabled(mapping) {
+                self.classify_mapping(mapping)?
+            } else {
+                Vec::new()
+            };
+
+            for (target_index, target) in mapping.targets().into_iter().enumerate() {
+                let color = if !self.is_enabled(mapping) {
+                    "gray"
+                } else {
+                    let target_path = self.target_path(mapping, target)?;
+                    let mut summary = StatusSummary::default();
+                    for (_, file_target, status) in &statuses {
+                        if file_target.starts_with(&target_path) {
+                            summary.add(*status);
+                        }
+                    }
+                    match summary.token() {
+                        "ok" => "green",
+                        "missing" => "orange",
+                        _ => "red",
+                    }
+                };
+
+                let target_id = format!("target{}_{}", index, target_index);
+                writeln!(
+                    dot,
+                    "    {} [label=\"{}{}\", color={}];",
+                    target_id,
+                    if Path::new(target).is_absolute() {
+                        ""
+                    } else {
+                        "~/"
+                    },
+                    escape(target),
+                    color
+                )?;
+                writeln!(dot, "    {} -> {};", source_id, target_id)?;
+            }
+        }
+
+        dot.push_str("}\n");
+        Ok(dot)
+    }
+}
+
+fn escape(label: &str) -> String {
+    label.replace('\\', "\\\\").replace('"', "\\\"")
+}
@@ -0,0 +1,79 @@
+use super::{shell_command, DotfileManager};
+use crate::config;
+use anyhow::{Context, Result};
+use colored::*;
+use std::fs;
+
+impl DotfileManager {
+    /// Runs the `[hooks] post_install` commands in order, with the variables
+    /// from `[secrets] env_file` in their environment. A dry run only lists
+    /// them and never reads the env file.
+    pub fn run_post_install_hooks(&self) -> Result<()> {
+        let config = self.config()?;
+        let hooks = &config.hooks.post_install;
+        if hooks.is_empty() {
+            return Ok(());
+        }
+
+        if self.options.dry_run {
+            for hook in hooks {
+                self.emitter
+                    .message(format!("{} {}", "Would run hook:".cyan(), hook));
+            }
+            return Ok(());
+        }
+
+        let env = self.hook_env()?;
+        for hook in hooks {
+            self.log(&format!("Running hook: {}", redact(hook, &env)));
+            let output = shell_command(hook)
+                .envs(env.iter().map(|(key, value)| (key, value)))
+                .output()
+                .with_context(|| format!("Failed to run hook `{}`", redact(hook, &env)))?;
+            if !output.status.success() {
+                anyhow::bail!(
+                    "Hook `{}` failed ({}): {}",
+                    redact(hook, &env),
+                    output.status,
+                    redact(String::from_utf8_lossy(&output.stderr).trim(), &env)
+                );
+            }
+            self.emitter
+                .message(format!("{} {}", "Ran hook:".green(), redact(hook, &env)));
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            if !stdout.trim().is_empty() {
+                self.emitter.message(redact(stdout.trim_end(), &env));
+            }
+        }
+        Ok(())
+    }
+
+    /// Variables from the secrets env file, or none if it isn't configured.
+    fn hook_env(&self) -> Result<Vec<(String, String)>> {
+        let Some(path) = self.config()?.secrets.env_file_path(&self.home_dir) else {
+            return Ok(Vec::new());
+        };
+        let contents = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read secrets env file {}", path.display()))?;
+        let env = config::parse_env_file(&contents)
+            .with_context(|| format!("Failed to parse {}", path.display()))?;
+        self.log(&format!(
+            "Loaded {} from {}",
+            env.iter()
+                .map(|(key, _)| key.as_str())
+                .collect::<Vec<_>>()
+                .join(", "),
+            path.display()
+        ));
+        Ok(env)
+    }
+}
+
+/// Hides every secret value that appears in `text`.
+fn redact(text: &str, env: &[(String, String)]) -> String {
+    env.iter()
+        .filter(|(_, value)| !value.is_empty())
+        .fold(text.to_string(), |text, (_, value)| {
+            text.replace(value.as_str(), "***")
+        })
+}
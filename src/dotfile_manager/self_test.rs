@@ -0,0 +1,126 @@
+use super::{DotfileManager, Options};
+use crate::config::Secrets;
+use crate::fsutil::copy_recursive;
+use anyhow::{Context, Result};
+use colored::*;
+use std::{env, fs, path::Path, process};
+
+impl DotfileManager {
+    /// Installs the current config into a throwaway home directory and
+    /// checks that every target ended up correct, then cleans up.
+    pub fn self_test(&self) -> Result<()> {
+        println!("{}", "Running self-test...".green().bold());
+
+        let config_path = self.resolve_config_path()?;
+        let root = env::temp_dir().join(format!(
+            "dotfiles-self-test-{}-{}",
+            process::id(),
+            chrono::Local::now().format("%Y%m%d%H%M%S")
+        ));
+
+        let result = self.run_self_test(&root, &config_path);
+        if root.exists() {
+            fs::remove_dir_all(&root).context("Failed to clean up self-test directory")?;
+        }
+        result
+    }
+
+    fn run_self_test(&self, root: &Path, config_path: &Path) -> Result<()> {
+        let fake_home = root.join("home");
+        let fake_dotfiles = root.join("dotfiles");
+        fs::create_dir_all(&fake_home)?;
+        fs::create_dir_all(&fake_dotfiles)?;
+
+        // Copy the config and every source it refers to
+        let format = self.config_format(config_path);
+        let fake_config = fake_dotfiles.join("config.toml");
+        fs::copy(config_path, &fake_config).context("Failed to copy config")?;
+        for mapping in &self.config()?.files {
+            let source = self.source_path(mapping)?;
+            let copies = if mapping.is_glob() {
+                // Each match, at the same place below the glob's root
+                let mut root = source.clone();
+                for _ in Path::new(&mapping.source).components() {
+                    root.pop();
+                }
+                self.resolve_files(mapping)?
+                    .into_iter()
+                    .filter_map(|(file, _)| {
+                        let relative = file.strip_prefix(&root).ok()?.to_path_buf();
+                        Some((file, fake_dotfiles.join(relative)))
+                    })
+                    .collect()
+            } else if source.exists() {
+                vec![(source, fake_dotfiles.join(&mapping.source))]
+            } else {
+                Vec::new()
+            };
+            for (source, dest) in copies {
+                if let Some(parent) = dest.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                copy_recursive(&source, &dest)?;
+            }
+        }
+
+        let sandbox = DotfileManager::new(Options {
+            verbose: self.options.verbose,
+            config: Some(fake_config),
+            home: Some(fake_home.clone()),
+            dotfiles_dir: Some(fake_dotfiles),
+            machine: self.options.machine.clone(),
+            config_format: Some(format),
+            os: self.options.os.clone(),
+            exclude_os: self.options.exclude_os.clone(),
+            ..Default::default()
+        })?;
+        // Hooks and the secrets command act on the real machine, so the
+        // sandbox runs neither, and reuses our `when` results rather than
+        // running the predicates again
+        let mut config = sandbox.load_config()?;
+        config.hooks.post_install.clear();
+        config.secrets = Secrets {
+            enabled: Some(false),
+            ..Default::default()
+        };
+        for mapping in &self.config()?.files {
+            self.is_enabled(mapping);
+        }
+        *sandbox.predicates.borrow_mut() = self.predicates.borrow().clone();
+        let _ = sandbox.config.set(config);
+        sandbox.install(None)?;
+
+        println!("\n{}", "Results:".blue().bold());
+        let mut failures = 0;
+        for mapping in sandbox
+            .config()?
+            .files
+            .iter()
+            .filter(|m| sandbox.is_enabled(m))
+        {
+            for (_, target, status) in sandbox.classify_mapping(mapping)? {
+                let shown = target.strip_prefix(&fake_home).unwrap_or(&target);
+                if status.is_installed() {
+                    println!("  {} ~/{}", "PASS".green(), shown.display());
+                } else {
+                    println!(
+                        "  {} ~/{} ({})",
+                        "FAIL".red(),
+                        shown.display(),
+                        status.label()
+                    );
+                    failures += 1;
+                }
+            }
+        }
+
+        if failures > 0 {
+            anyhow::bail!(
+                "Self-test failed: {} target(s) not installed correctly",
+                failures
+            );
+        }
+        println!("\n{}", "Self-test passed!".green());
+        Ok(())
+    }
+}
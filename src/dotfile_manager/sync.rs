@@ -0,0 +1,168 @@
+use super::DotfileManager;
+use crate::config::FileMapping;
+use anyhow::{Context, Result};
+use colored::*;
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+impl DotfileManager {
+    /// Pulls the dotfiles repo (fast-forward only) and reinstalls. With
+    /// `dry_run`, only fetches, lists the incoming commits with the files
+    /// they touch, and shows the install plan for mappings whose sources
+    /// change, without pulling or installing anything.
+    pub fn sync(&self, dry_run: bool) -> Result<()> {
+        self.git(&["fetch", "--quiet"])?;
+        // Relative to the dotfiles directory, which may be below the repo root
+        let changed = self.git(&["diff", "--name-only", "--relative", "HEAD...@{u}"])?;
+        let changed: Vec<&str> = changed.lines().filter(|line| !line.is_empty()).collect();
+        if changed.is_empty() {
+            println!("Already up to date");
+            if dry_run {
+                return Ok(());
+            }
+        }
+
+        if dry_run {
+            println!("{}", "Incoming changes:".blue().bold());
+            print!(
+                "{}",
+                self.git(&["log", "--name-only", "--format=%h %s", "HEAD..@{u}"])?
+            );
+            let config_path = self.resolve_config_path()?;
+            if changed
+                .iter()
+                .any(|path| self.dotfiles_dir.join(path) == config_path)
+            {
+                println!(
+                    "\n{}",
+                    "The config changes too; this plan uses the current one".yellow()
+                );
+            }
+
+            println!("\n{}", "Install plan:".blue().bold());
+            let config = self.config()?;
+            let affected: Vec<&FileMapping> = config
+                .files
+                .iter()
+                .filter(|mapping| self.is_enabled(mapping) && touches(mapping, &changed))
+                .collect();
+            if affected.is_empty() {
+                println!("No mapped sources change");
+            }
+            self.planning.set(true);
+            let planned = affected
+                .iter()
+                .try_for_each(|mapping| self.install_mapping(mapping).map(drop));
+            self.planning.set(false);
+            planned?;
+            // The plan only covers files already checked out
+            for path in &changed {
+                let arriving = !self.dotfiles_dir.join(path).exists();
+                if arriving && affected.iter().any(|mapping| touches(mapping, &[path])) {
+                    println!("{}: {}", "new with the pull".cyan(), path);
+                }
+            }
+            return Ok(());
+        }
+
+        if !changed.is_empty() {
+            self.git(&["pull", "--ff-only", "--quiet"])?;
+            println!("Pulled {} changed file(s)", changed.len());
+        }
+        // A fresh manager picks up a config the pull changed
+        DotfileManager::new(self.options.clone())?.install(None)
+    }
+
+    /// Runs git in the dotfiles repo and returns its stdout.
+    fn git(&self, args: &[&str]) -> Result<String> {
+        git(&self.dotfiles_dir, args)
+    }
+}
+
+/// Exports the dotfiles directory as it was at `git_ref` into a temporary
+/// directory named after the commit, and returns that directory. Only reads
+/// from git. An export that already exists is reused, since a commit's tree
+/// never changes.
+pub(super) fn export_ref(dotfiles_dir: &Path, git_ref: &str) -> Result<PathBuf> {
+    if git(dotfiles_dir, &["rev-parse", "--git-dir"]).is_err() {
+        anyhow::bail!(
+            "--config-from-git needs {} to be a git repository",
+            dotfiles_dir.display()
+        );
+    }
+    let commit = git(
+        dotfiles_dir,
+        &["rev-parse", "--verify", &format!("{}^{{commit}}", git_ref)],
+    )?;
+    let commit = commit.trim();
+    // The dotfiles directory may be below the repo root
+    let prefix = git(dotfiles_dir, &["rev-parse", "--show-prefix"])?;
+
+    let export = env::temp_dir().join(format!("dotfiles-{}", &commit[..12]));
+    if export.is_dir() {
+        return Ok(export);
+    }
+    let partial = export.with_extension("partial");
+    if partial.exists() {
+        fs::remove_dir_all(&partial)?;
+    }
+    fs::create_dir_all(&partial)?;
+
+    let mut archive = Command::new("git")
+        .arg("-C")
+        .arg(dotfiles_dir)
+        .args(["archive", "--format=tar"])
+        .arg(format!("{}:{}", commit, prefix.trim()))
+        .stdout(Stdio::piped())
+        .spawn()
+        .context("Failed to run git")?;
+    let stdout = archive
+        .stdout
+        .take()
+        .context("Failed to read git archive")?;
+    let extracted = Command::new("tar")
+        .arg("-x")
+        .arg("-C")
+        .arg(&partial)
+        .stdin(stdout)
+        .status()
+        .context("Failed to run tar")?;
+    if !archive.wait()?.success() || !extracted.success() {
+        anyhow::bail!("Failed to export {} from git", git_ref);
+    }
+    fs::rename(&partial, &export)?;
+    Ok(export)
+}
+
+/// Runs git in `dir` and returns its stdout.
+pub(super) fn git(dir: &Path, args: &[&str]) -> Result<String> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .output()
+        .context("Failed to run git")?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "git {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Whether any repo-relative `changed` path is the mapping's source or lies
+/// inside it.
+fn touches(mapping: &FileMapping, changed: &[&str]) -> bool {
+    let source = mapping.source.trim_end_matches('/');
+    changed.iter().any(|path| {
+        *path == source
+            || path
+                .strip_prefix(source)
+                .is_some_and(|rest| rest.starts_with('/'))
+    })
+}
@@ -0,0 +1,460 @@
+use super::{DotfileManager, Options};
+use crate::status::TargetStatus;
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+/// A fresh `home` and `dotfiles` pair under the temp directory, unique to
+/// the test and process.
+fn scratch(name: &str) -> PathBuf {
+    let root = env::temp_dir().join(format!("dotfiles-test-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&root);
+    fs::create_dir_all(root.join("home")).unwrap();
+    fs::create_dir_all(root.join("dotfiles")).unwrap();
+    fs::canonicalize(root).unwrap()
+}
+
+fn write(path: &Path, contents: &str) {
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(path, contents).unwrap();
+}
+
+/// A manager for `root`'s home, reading `dotfiles_dir`'s config.
+fn manager(root: &Path, dotfiles_dir: &Path, options: Options) -> DotfileManager {
+    DotfileManager::new(Options {
+        home: Some(root.join("home")),
+        dotfiles_dir: Some(dotfiles_dir.to_path_buf()),
+        ..options
+    })
+    .unwrap()
+}
+
+fn statuses(manager: &DotfileManager) -> Vec<TargetStatus> {
+    let config = manager.config().unwrap();
+    config
+        .files
+        .iter()
+        .flat_map(|mapping| manager.classify_mapping(mapping).unwrap())
+        .map(|(_, _, status)| status)
+        .collect()
+}
+
+#[cfg(unix)]
+#[test]
+fn symlinked_dotfiles_dir_links_into_the_real_repo() {
+    let root = scratch("symlinked-repo");
+    let repo = root.join("dotfiles");
+    write(
+        &repo.join("config.toml"),
+        "[[files]]\nsource = \"zshrc\"\ntarget = \".zshrc\"\n",
+    );
+    write(&repo.join("zshrc"), "export EDITOR=nvim\n");
+    let link = root.join("dotfiles-link");
+    std::os::unix::fs::symlink(&repo, &link).unwrap();
+
+    let installer = manager(&root, &link, Options::default());
+    installer.install(None).unwrap();
+    assert_eq!(
+        fs::read_link(root.join("home/.zshrc")).unwrap(),
+        repo.join("zshrc")
+    );
+    assert_eq!(statuses(&installer), [TargetStatus::Linked]);
+
+    // Still linked when looked at through the symlink
+    let uncanonical = manager(
+        &root,
+        &link,
+        Options {
+            no_canonicalize: true,
+            ..Default::default()
+        },
+    );
+    assert_eq!(statuses(&uncanonical), [TargetStatus::Linked]);
+    let _ = fs::remove_dir_all(&root);
+}
+
+#[cfg(unix)]
+#[test]
+fn backup_dir_is_private() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let root = scratch("backup-mode");
+    let repo = root.join("dotfiles");
+    write(
+        &repo.join("config.toml"),
+        "[[files]]\nsource = \"zshrc\"\ntarget = \".zshrc\"\n",
+    );
+    write(&repo.join("zshrc"), "export EDITOR=nvim\n");
+    write(&root.join("home/.zshrc"), "export EDITOR=vi\n");
+
+    let installer = manager(&root, &repo, Options::default());
+    installer.install(None).unwrap();
+    let mode = fs::metadata(&installer.backup_dir)
+        .unwrap()
+        .permissions()
+        .mode();
+    assert_eq!(mode & 0o777, 0o700);
+    assert_eq!(
+        fs::read_to_string(installer.backup_dir.join(".zshrc")).unwrap(),
+        "export EDITOR=vi\n"
+    );
+    let _ = fs::remove_dir_all(&root);
+}
+
+#[cfg(unix)]
+#[test]
+fn copied_tree_keeps_file_modes() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let root = scratch("copy-modes");
+    let repo = root.join("dotfiles");
+    write(
+        &repo.join("config.toml"),
+        "[[files]]\nsource = \"bin\"\ntarget = \".local/bin\"\nstrategy = \"copy\"\n",
+    );
+    for (name, mode) in [("notes.txt", 0o644), ("sub/deploy", 0o755)] {
+        let path = repo.join("bin").join(name);
+        write(&path, "#!/bin/sh\n");
+        fs::set_permissions(&path, fs::Permissions::from_mode(mode)).unwrap();
+    }
+
+    manager(&root, &repo, Options::default())
+        .install(None)
+        .unwrap();
+    for (name, mode) in [("notes.txt", 0o644), ("sub/deploy", 0o755)] {
+        let target = root.join("home/.local/bin").join(name);
+        assert!(!target.is_symlink());
+        let metadata = fs::metadata(&target).unwrap();
+        assert_eq!(metadata.permissions().mode() & 0o777, mode, "{}", name);
+    }
+    let _ = fs::remove_dir_all(&root);
+}
+
+#[cfg(unix)]
+#[test]
+fn trailing_slash_target_places_inside_the_directory() {
+    let root = scratch("target-inside");
+    let repo = root.join("dotfiles");
+    write(
+        &repo.join("config.toml"),
+        "[[files]]\nsource = \"nvim/init.lua\"\ntarget = \".config/nvim/\"\n",
+    );
+    write(&repo.join("nvim/init.lua"), "vim.o.number = true\n");
+
+    manager(&root, &repo, Options::default())
+        .install(None)
+        .unwrap();
+    assert_eq!(
+        fs::read_link(root.join("home/.config/nvim/init.lua")).unwrap(),
+        repo.join("nvim/init.lua")
+    );
+    assert!(!root.join("home/.config/nvim").is_symlink());
+    let _ = fs::remove_dir_all(&root);
+}
+
+#[cfg(unix)]
+#[test]
+fn target_without_trailing_slash_is_the_exact_path() {
+    let root = scratch("target-exact");
+    let repo = root.join("dotfiles");
+    write(
+        &repo.join("config.toml"),
+        "[[files]]\nsource = \"nvim/init.lua\"\ntarget = \".config/nvim/config.lua\"\n",
+    );
+    write(&repo.join("nvim/init.lua"), "vim.o.number = true\n");
+
+    manager(&root, &repo, Options::default())
+        .install(None)
+        .unwrap();
+    assert_eq!(
+        fs::read_link(root.join("home/.config/nvim/config.lua")).unwrap(),
+        repo.join("nvim/init.lua")
+    );
+    assert!(!root.join("home/.config/nvim/init.lua").exists());
+    let _ = fs::remove_dir_all(&root);
+}
+
+#[test]
+fn failed_config_write_leaves_config_intact() {
+    let root = scratch("config-write");
+    let path = root.join("dotfiles/config.toml");
+    let original = "[[files]]\nsource = \"zshrc\"\ntarget = \".zshrc\"\n";
+    write(&path, original);
+    // A directory squatting on the temp file's name makes the write fail
+    let squatter = root.join("dotfiles/.config.toml.tmp");
+    fs::create_dir(&squatter).unwrap();
+
+    let mut doc: toml_edit::DocumentMut = original.parse().unwrap();
+    doc["files"][0]["target"] = toml_edit::value(".zprofile");
+    assert!(super::write_config(&path, &doc).is_err());
+    assert_eq!(fs::read_to_string(&path).unwrap(), original);
+    assert!(squatter.is_dir());
+    let _ = fs::remove_dir_all(&root);
+}
+
+#[test]
+fn enabled_mappings_sharing_a_target_are_rejected() {
+    let root = scratch("duplicate-targets");
+    let repo = root.join("dotfiles");
+    write(
+        &repo.join("config.toml"),
+        "[[files]]\nsource = \"zshrc\"\ntarget = \"~/.zshrc\"\n\n\
+         [[files]]\nsource = \"zshrc.local\"\ntarget = \".zshrc\"\n",
+    );
+    write(&repo.join("zshrc"), "export EDITOR=nvim\n");
+    write(&repo.join("zshrc.local"), "export EDITOR=vi\n");
+
+    let err = manager(&root, &repo, Options::default())
+        .install(None)
+        .unwrap_err();
+    assert!(
+        format!("{:#}", err).contains("used by more than one mapping"),
+        "{:#}",
+        err
+    );
+    assert!(fs::symlink_metadata(root.join("home/.zshrc")).is_err());
+    let _ = fs::remove_dir_all(&root);
+}
+
+#[cfg(unix)]
+#[test]
+fn mappings_never_active_together_may_share_a_target() {
+    let root = scratch("alternative-targets");
+    let repo = root.join("dotfiles");
+    write(
+        &repo.join("config.toml"),
+        "[[files]]\nsource = \"foo-linux\"\ntarget = \".config/foo\"\nos = [\"linux\"]\n\n\
+         [[files]]\nsource = \"foo-macos\"\ntarget = \".config/foo\"\nos = [\"macos\"]\n\n\
+         [[files]]\nsource = \"foo-fallback\"\ntarget = \".config/foo\"\nenabled = false\n",
+    );
+    for source in ["foo-linux", "foo-macos", "foo-fallback"] {
+        write(&repo.join(source), source);
+    }
+
+    manager(
+        &root,
+        &repo,
+        Options {
+            os: Some("linux".to_string()),
+            ..Default::default()
+        },
+    )
+    .install(None)
+    .unwrap();
+    assert_eq!(
+        fs::read_link(root.join("home/.config/foo")).unwrap(),
+        repo.join("foo-linux")
+    );
+    let _ = fs::remove_dir_all(&root);
+}
+
+#[cfg(unix)]
+#[test]
+fn moved_file_is_linked_back_and_recorded() {
+    let root = scratch("add-moved");
+    let repo = root.join("dotfiles");
+    write(&repo.join("config.toml"), "");
+    let file = root.join("home/.gitconfig");
+    write(&file, "[user]\n");
+
+    manager(&root, &repo, Options::default())
+        .add_moved("git", &file)
+        .unwrap();
+    assert_eq!(fs::read_link(&file).unwrap(), repo.join("git/.gitconfig"));
+    let meta = crate::state::TopicMeta::load(&repo.join("git")).unwrap();
+    assert_eq!(meta.files[".gitconfig"], file);
+    let _ = fs::remove_dir_all(&root);
+}
+
+#[test]
+fn failed_move_puts_the_file_back() {
+    let root = scratch("add-moved-fails");
+    let repo = root.join("dotfiles");
+    write(&repo.join("config.toml"), "");
+    fs::create_dir(repo.join(".config.toml.tmp")).unwrap();
+    let file = root.join("home/.gitconfig");
+    write(&file, "[user]\n");
+
+    assert!(manager(&root, &repo, Options::default())
+        .add_moved("git", &file)
+        .is_err());
+    assert_eq!(fs::read_to_string(&file).unwrap(), "[user]\n");
+    assert!(fs::symlink_metadata(repo.join("git/.gitconfig")).is_err());
+    let _ = fs::remove_dir_all(&root);
+}
+
+#[cfg(unix)]
+#[test]
+fn remove_that_cannot_edit_the_config_leaves_links() {
+    let root = scratch("remove-uneditable");
+    let repo = root.join("dotfiles");
+    write(
+        &repo.join("config.toml"),
+        r#"{"files": [{"source": "git/config", "target": ".gitconfig"}]}"#,
+    );
+    write(&repo.join("git/config"), "[user]\n");
+    let manager = manager(
+        &root,
+        &repo,
+        Options {
+            config_format: Some(crate::config::ConfigFormat::Json),
+            ..Options::default()
+        },
+    );
+    manager.install(None).unwrap();
+
+    let err = manager
+        .remove_config("git", "config", false, false)
+        .unwrap_err();
+    assert!(
+        err.to_string().contains("only supported for TOML"),
+        "{}",
+        err
+    );
+    let link = root.join("home/.gitconfig");
+    assert_eq!(fs::read_link(&link).unwrap(), repo.join("git/config"));
+    assert!(repo.join("git/config").is_file());
+    let _ = fs::remove_dir_all(&root);
+}
+
+#[cfg(unix)]
+#[test]
+fn kept_directory_target_becomes_a_copy() {
+    let root = scratch("keep-target-dir");
+    let repo = root.join("dotfiles");
+    write(
+        &repo.join("config.toml"),
+        "[[files]]\nsource = \"nvim/lua\"\ntarget = \".config/nvim/lua\"\n",
+    );
+    write(&repo.join("nvim/lua/init.lua"), "vim.o.number = true\n");
+    let manager = manager(&root, &repo, Options::default());
+    manager.install(None).unwrap();
+
+    manager.remove_config("nvim", "lua", false, true).unwrap();
+    let target = root.join("home/.config/nvim/lua");
+    assert!(!fs::symlink_metadata(&target)
+        .unwrap()
+        .file_type()
+        .is_symlink());
+    assert_eq!(
+        fs::read_to_string(target.join("init.lua")).unwrap(),
+        "vim.o.number = true\n"
+    );
+    assert!(fs::symlink_metadata(root.join("home/.config/nvim/.lua.tmp")).is_err());
+    let _ = fs::remove_dir_all(&root);
+}
+
+#[test]
+fn clean_counts_archived_runs() {
+    let root = scratch("clean-archives");
+    let repo = root.join("dotfiles");
+    let manager = manager(&root, &repo, Options::default());
+    let backups = root.join("home/.dotfiles_backup");
+    write(&backups.join("20200101_000000.tar.gz"), "old");
+    fs::create_dir_all(backups.join("20200102_000000")).unwrap();
+    write(&backups.join("20200103_000000.tar.gz"), "newest");
+
+    manager.clean(Some(1), None, false).unwrap();
+    assert!(backups.join("20200103_000000.tar.gz").is_file());
+    assert!(!backups.join("20200102_000000").exists());
+    assert!(!backups.join("20200101_000000.tar.gz").exists());
+    let _ = fs::remove_dir_all(&root);
+}
+
+#[cfg(unix)]
+#[test]
+fn restore_brings_back_dangling_symlinks() {
+    let root = scratch("restore-dangling");
+    let repo = root.join("dotfiles");
+    let manager = manager(&root, &repo, Options::default());
+    let run = root.join("home/.dotfiles_backup/20200101_000000");
+    fs::create_dir_all(&run).unwrap();
+    let original = root.join("home/.zshrc");
+    std::os::unix::fs::symlink("/nonexistent/zshrc", run.join(".zshrc")).unwrap();
+    let manifest = crate::state::Manifest {
+        backups: vec![crate::state::BackupRecord {
+            original: original.clone(),
+            backup: run.join(".zshrc"),
+        }],
+    };
+    write(
+        &run.join("manifest.toml"),
+        &toml::to_string(&manifest).unwrap(),
+    );
+
+    manager.restore(Some("20200101_000000")).unwrap();
+    assert_eq!(
+        fs::read_link(&original).unwrap(),
+        Path::new("/nonexistent/zshrc")
+    );
+    let _ = fs::remove_dir_all(&root);
+}
+
+#[cfg(unix)]
+#[test]
+fn unmapped_topic_install_writes_report_and_reverse_script() {
+    let root = scratch("topic-target-tail");
+    let repo = root.join("dotfiles");
+    write(&repo.join("config.toml"), "");
+    write(&repo.join("kitty/kitty.conf"), "font_size 12\n");
+    let report = root.join("report.json");
+    let reverse = root.join("undo.sh");
+    let manager = manager(
+        &root,
+        &repo,
+        Options {
+            topic_targets: [("kitty".to_string(), ".config/kitty".to_string())].into(),
+            report: Some(report.clone()),
+            report_format: crate::report::ReportFormat::Json,
+            reverse_script: Some(reverse.clone()),
+            ..Options::default()
+        },
+    );
+
+    manager.install(Some("kitty")).unwrap();
+    let linked = root.join("home/.config/kitty/kitty.conf");
+    assert_eq!(
+        fs::read_link(&linked).unwrap(),
+        repo.join("kitty/kitty.conf")
+    );
+    assert!(fs::read_to_string(&report).unwrap().contains("\"kitty\""));
+    let script = fs::read_to_string(&reverse).unwrap();
+    assert!(script.contains(&linked.display().to_string()), "{}", script);
+    assert!(root.join("home/.dotfiles_backup/audit.log").is_file());
+    let _ = fs::remove_dir_all(&root);
+}
+
+#[cfg(unix)]
+#[test]
+fn reverse_script_lists_targets_replaced_without_backup() {
+    let root = scratch("reverse-no-backup");
+    let repo = root.join("dotfiles");
+    write(
+        &repo.join("config.toml"),
+        "[[files]]\nsource = \"zshrc\"\ntarget = \".zshrc\"\n",
+    );
+    write(&repo.join("zshrc"), "export EDITOR=nvim\n");
+    let target = root.join("home/.zshrc");
+    write(&target, "export EDITOR=vi\n");
+    let reverse = root.join("undo.sh");
+    manager(
+        &root,
+        &repo,
+        Options {
+            no_backup: true,
+            reverse_script: Some(reverse.clone()),
+            ..Options::default()
+        },
+    )
+    .install(None)
+    .unwrap();
+
+    let script = fs::read_to_string(&reverse).unwrap();
+    let undo_link = format!("rm '{}'", target.display());
+    let note = format!("# cannot restore {}: no backup", target.display());
+    let (link_at, note_at) = (script.find(&undo_link), script.find(&note));
+    assert!(link_at.is_some() && note_at.is_some(), "{}", script);
+    assert!(link_at < note_at, "{}", script);
+    let _ = fs::remove_dir_all(&root);
+}
@@ -0,0 +1,69 @@
+use super::DotfileManager;
+use anyhow::Result;
+use colored::*;
+use std::collections::BTreeMap;
+
+/// Target directories nested by path component, with the mappings that
+/// place a target directly inside each one.
+#[derive(Default)]
+struct Node {
+    dirs: BTreeMap<String, Node>,
+    /// (target name, source, status)
+    targets: Vec<(String, String, ColoredString)>,
+}
+
+impl DotfileManager {
+    /// Like [`DotfileManager::list_configs`], but groups targets under the
+    /// directories they are placed in.
+    pub fn list_tree(&self) -> Result<()> {
+        let config = self.config()?;
+        let mut root = Node::default();
+
+        for mapping in &config.files {
+            for target in mapping.targets() {
+                let target = self.target_path(mapping, target)?;
+                let status = if !self.is_enabled(mapping) {
+                    "disabled".dimmed()
+                } else if target.exists() {
+                    "installed".green()
+                } else {
+                    "not installed".yellow()
+                };
+
+                let relative = target.strip_prefix(&self.home_dir).ok();
+                let shown = relative.unwrap_or(&target);
+                let mut node = &mut root;
+                if let Some(parent) = shown.parent() {
+                    for component in parent.iter() {
+                        node = node
+                            .dirs
+                            .entry(component.to_string_lossy().into_owned())
+                            .or_default();
+                    }
+                }
+                let name = shown.file_name().map_or_else(
+                    || shown.display().to_string(),
+                    |name| name.to_string_lossy().into_owned(),
+                );
+                node.targets.push((name, mapping.source.clone(), status));
+            }
+        }
+
+        println!("{}", "~".blue().bold());
+        print_node(&root, 1);
+        Ok(())
+    }
+}
+
+fn print_node(node: &Node, depth: usize) {
+    let indent = "  ".repeat(depth);
+    for (name, child) in &node.dirs {
+        // Targets outside home hang off a "/" node
+        let name = format!("{}/", name.trim_end_matches('/'));
+        println!("{}{}", indent, name.blue().bold());
+        print_node(child, depth + 1);
+    }
+    for (name, source, status) in &node.targets {
+        println!("{}{} <- {} [{}]", indent, name, source, status);
+    }
+}
@@ -0,0 +1,172 @@
+use super::DotfileManager;
+use crate::events::Event;
+use crate::filter::glob_match;
+use anyhow::Result;
+use colored::*;
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+
+/// How often the watched files are checked for changes.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Modification time of every watched file.
+type Snapshot = BTreeMap<PathBuf, Option<SystemTime>>;
+
+impl DotfileManager {
+    /// Reinstalls whenever a file in the dotfiles repo or the config changes,
+    /// until interrupted. A burst of changes is coalesced into one run that
+    /// starts once nothing has changed for `debounce`. Paths matching an
+    /// `ignore` glob (against the repo-relative path or the file name) never
+    /// trigger a run.
+    pub fn watch(&self, topic: Option<&str>, debounce: Duration, ignore: &[String]) -> Result<()> {
+        self.emitter.message(format!(
+            "{} {} (Ctrl-C to stop)",
+            "Watching".blue().bold(),
+            self.dotfiles_dir.display()
+        ));
+        let mut seen = self.snapshot(ignore)?;
+        loop {
+            thread::sleep(POLL_INTERVAL);
+            let mut current = self.snapshot(ignore)?;
+            if current == seen {
+                continue;
+            }
+
+            let mut quiet_since = Instant::now();
+            while quiet_since.elapsed() < debounce {
+                thread::sleep(POLL_INTERVAL.min(debounce));
+                let next = self.snapshot(ignore)?;
+                if next != current {
+                    current = next;
+                    quiet_since = Instant::now();
+                }
+            }
+
+            self.emitter.message("");
+            for path in changed_paths(&seen, &current) {
+                self.emitter
+                    .message(format!("{} {}", "Changed:".blue(), path.display()));
+            }
+            seen = current;
+
+            // A fresh manager rereads the config and backs up into a new directory
+            let result = DotfileManager::new(self.options.clone())
+                .and_then(|manager| manager.install(topic));
+            if let Err(err) = result {
+                self.emitter.emit(&Event::Warning {
+                    message: &format!("Reinstall failed: {:#}", err),
+                });
+            }
+        }
+    }
+
+    /// Redraws the status every `interval`, and as soon as the repo or config
+    /// changes, until interrupted. `deep` is passed on to each status.
+    pub fn watch_status(&self, interval: Duration, deep: bool) -> Result<()> {
+        loop {
+            // Clear the screen and move to the top left
+            print!("\x1b[2J\x1b[H");
+            // A fresh manager rereads the config
+            let result = DotfileManager::new(self.options.clone())
+                .and_then(|manager| manager.check_status(deep));
+            if let Err(err) = result {
+                println!("{}", format!("Status failed: {:#}", err).red());
+            }
+            println!(
+                "\n{}",
+                format!(
+                    "Updated {} (Ctrl-C to stop)",
+                    chrono::Local::now().format("%H:%M:%S")
+                )
+                .dimmed()
+            );
+
+            let seen = self.snapshot(&[])?;
+            let started = Instant::now();
+            while started.elapsed() < interval {
+                thread::sleep(POLL_INTERVAL);
+                if self.snapshot(&[])? != seen {
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Every file under the dotfiles directory, plus the config file, except
+    /// `.git` and anything matching `ignore`.
+    fn snapshot(&self, ignore: &[String]) -> Result<Snapshot> {
+        let mut snapshot = Snapshot::new();
+        collect_mtimes(
+            &self.dotfiles_dir,
+            &self.dotfiles_dir,
+            ignore,
+            &mut snapshot,
+        )?;
+        if let Ok(config_path) = self.resolve_config_path() {
+            let modified = fs::metadata(&config_path).and_then(|m| m.modified()).ok();
+            snapshot.insert(config_path, modified);
+        }
+        Ok(snapshot)
+    }
+}
+
+fn collect_mtimes(
+    root: &Path,
+    dir: &Path,
+    ignore: &[String],
+    snapshot: &mut Snapshot,
+) -> Result<()> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        // Removed while being watched
+        return Ok(());
+    };
+    for entry in entries {
+        let entry = entry?;
+        let path = entry.path();
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let relative = path
+            .strip_prefix(root)
+            .unwrap_or(&path)
+            .to_string_lossy()
+            .replace('\\', "/");
+        if name == ".git"
+            || ignore
+                .iter()
+                .any(|pattern| glob_match(pattern, &relative) || glob_match(pattern, &name))
+        {
+            continue;
+        }
+
+        let Ok(metadata) = fs::symlink_metadata(&path) else {
+            continue;
+        };
+        if metadata.is_dir() {
+            collect_mtimes(root, &path, ignore, snapshot)?;
+        } else {
+            snapshot.insert(path, metadata.modified().ok());
+        }
+    }
+    Ok(())
+}
+
+/// Paths added, removed or modified between two snapshots.
+fn changed_paths<'a>(before: &'a Snapshot, after: &'a Snapshot) -> Vec<&'a Path> {
+    let mut changed: Vec<&Path> = after
+        .iter()
+        .filter(|(path, modified)| before.get(*path) != Some(modified))
+        .map(|(path, _)| path.as_path())
+        .collect();
+    changed.extend(
+        before
+            .keys()
+            .filter(|path| !after.contains_key(*path))
+            .map(PathBuf::as_path),
+    );
+    changed.sort();
+    changed
+}
@@ -0,0 +1,274 @@
+use anyhow::{Context, Result};
+use colored::*;
+use serde::Serialize;
+use std::{
+    cell::{Cell, RefCell},
+    fmt::Display,
+    fs::{File, OpenOptions},
+    io::Write,
+    path::Path,
+};
+
+/// Something install did. Rendered as a console line, or as one JSON object
+/// per line when `--events` is set.
+#[derive(Serialize, Debug)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event<'a> {
+    Linked {
+        source: &'a Path,
+        target: &'a Path,
+    },
+    Rendered {
+        source: &'a Path,
+        target: &'a Path,
+    },
+    Copied {
+        source: &'a Path,
+        target: &'a Path,
+    },
+    BackedUp {
+        target: &'a Path,
+        backup: &'a Path,
+    },
+    Removed {
+        target: &'a Path,
+    },
+    Skipped {
+        source: &'a Path,
+        target: &'a Path,
+    },
+    Warning {
+        message: &'a str,
+    },
+    /// How `--dry-run` expects a target to change, e.g. `will create (new)`.
+    Delta {
+        change: &'a str,
+        source: &'a Path,
+        target: &'a Path,
+    },
+    /// An action `--dry-run` would have taken.
+    Planned {
+        action: &'a str,
+        source: Option<&'a Path>,
+        target: &'a Path,
+    },
+    Timing {
+        source: &'a str,
+        millis: f64,
+    },
+    TotalTime {
+        millis: f64,
+    },
+}
+
+/// One line of the audit log: a change install made to the filesystem.
+#[derive(Serialize)]
+struct AuditRecord<'a> {
+    timestamp: String,
+    action: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    source: Option<&'a Path>,
+    target: &'a Path,
+}
+
+/// Single place install output goes through, so human and machine consumers
+/// see the same events.
+#[derive(Debug, Default)]
+pub struct Emitter {
+    json: bool,
+    /// Keep stdout free for a single value: only warnings are printed, to
+    /// stderr.
+    quiet: bool,
+    audit: RefCell<Option<File>>,
+    /// How many warnings have been emitted, for `--fail-on-warning`.
+    warnings: Cell<usize>,
+    /// Console lines held back by [`Emitter::hold`] until the running
+    /// mapping finishes.
+    held: RefCell<Option<Vec<String>>>,
+}
+
+impl Emitter {
+    pub fn new(json: bool) -> Self {
+        Self {
+            json,
+            ..Default::default()
+        }
+    }
+
+    /// An emitter that prints nothing to stdout.
+    pub fn quiet() -> Self {
+        Self {
+            quiet: true,
+            ..Default::default()
+        }
+    }
+
+    /// Also appends every change made from now on to `path`, one JSON record
+    /// per line.
+    pub fn open_audit_log(&self, path: &Path) -> Result<()> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("Failed to open audit log {}", path.display()))?;
+        *self.audit.borrow_mut() = Some(file);
+        Ok(())
+    }
+
+    pub fn warning_count(&self) -> usize {
+        self.warnings.get()
+    }
+
+    /// Whether free-form text may be printed to stdout.
+    pub fn prints_text(&self) -> bool {
+        !self.json && !self.quiet
+    }
+
+    pub fn emit(&self, event: &Event) {
+        self.audit(event);
+        if let Event::Warning { .. } = event {
+            self.warnings.set(self.warnings.get() + 1);
+        }
+
+        if self.quiet {
+            if let Event::Warning { message } = event {
+                eprintln!("{}", message.yellow());
+            }
+            return;
+        }
+
+        if self.json {
+            match serde_json::to_string(event) {
+                Ok(line) => println!("{}", line),
+                Err(err) => eprintln!("Failed to serialize event: {}", err),
+            }
+            return;
+        }
+
+        match event {
+            Event::Linked { source, target } => self.print(format!(
+                "{} {:?} -> {:?}",
+                "Linked:".green(),
+                source,
+                target
+            )),
+            Event::Rendered { source, target } => self.print(format!(
+                "{} {:?} -> {:?}",
+                "Rendered:".green(),
+                source,
+                target
+            )),
+            Event::Copied { source, target } => self.print(format!(
+                "{} {:?} -> {:?}",
+                "Copied:".green(),
+                source,
+                target
+            )),
+            Event::BackedUp { target, .. } => {
+                self.print(format!("{} {:?}", "Backed up:".yellow(), target))
+            }
+            Event::Removed { target } => {
+                self.print(format!("{} {:?}", "Removed:".yellow(), target))
+            }
+            Event::Skipped { .. } => {}
+            Event::Warning { message } => println!("{}", message.yellow()),
+            Event::Delta {
+                change,
+                source,
+                target,
+            } => {
+                let change = if change.starts_with("no change") {
+                    change.dimmed()
+                } else {
+                    change.cyan()
+                };
+                self.print(format!("{}: {:?} -> {:?}", change, source, target))
+            }
+            Event::Planned {
+                action,
+                source: Some(source),
+                target,
+            } => self.print(format!(
+                "{} {:?} -> {:?}",
+                format!("Would {}:", action).cyan(),
+                source,
+                target
+            )),
+            Event::Planned {
+                action,
+                source: None,
+                target,
+            } => self.print(format!(
+                "{} {:?}",
+                format!("Would {}:", action).cyan(),
+                target
+            )),
+            Event::Timing { source, millis } => {
+                self.print(format!("  {:>10.2}ms  {}", millis, source))
+            }
+            Event::TotalTime { millis } => {
+                self.print(format!("  {:>10.2}ms  {}", millis, "total".bold()))
+            }
+        }
+    }
+
+    fn audit(&self, event: &Event) {
+        let (action, source, target) = match event {
+            Event::Linked { source, target } => ("linked", Some(*source), *target),
+            Event::Rendered { source, target } => ("rendered", Some(*source), *target),
+            Event::Copied { source, target } => ("copied", Some(*source), *target),
+            // The backup is where the original now lives, so it is the "target"
+            Event::BackedUp { target, backup } => ("backed_up", Some(*target), *backup),
+            Event::Removed { target } => ("removed", None, *target),
+            _ => return,
+        };
+
+        let mut audit = self.audit.borrow_mut();
+        let Some(file) = audit.as_mut() else {
+            return;
+        };
+        let record = AuditRecord {
+            timestamp: chrono::Local::now().to_rfc3339(),
+            action,
+            source,
+            target,
+        };
+        let written = serde_json::to_string(&record)
+            .map_err(anyhow::Error::from)
+            .and_then(|line| Ok(writeln!(file, "{}", line)?));
+        if let Err(err) = written {
+            eprintln!("Failed to write audit log: {}", err);
+        }
+    }
+
+    /// Prints free-form progress text; suppressed in JSON and quiet modes.
+    pub fn message(&self, text: impl Display) {
+        if self.prints_text() {
+            self.print(text);
+        }
+    }
+
+    /// Starts holding back console output (for `--output-on-error`) until
+    /// [`Emitter::release`]. Warnings and JSON events still go out at once.
+    pub fn hold(&self) {
+        *self.held.borrow_mut() = Some(Vec::new());
+    }
+
+    /// Stops holding output back, printing what was held if `flush` is set
+    /// and dropping it otherwise.
+    pub fn release(&self, flush: bool) {
+        let held = self.held.borrow_mut().take().unwrap_or_default();
+        if flush {
+            for line in held {
+                println!("{}", line);
+            }
+        }
+    }
+
+    fn print(&self, line: impl Display) {
+        match self.held.borrow_mut().as_mut() {
+            Some(held) => held.push(line.to_string()),
+            None => println!("{}", line),
+        }
+    }
+}
@@ -0,0 +1,145 @@
+use anyhow::{Context, Result};
+use std::{fs, path::Path};
+
+/// Which entries of one source directory get installed, read from the
+/// `.dotinclude` and `.dotexclude` files inside it. Each holds one glob per
+/// line (`*` and `?`, matched against entry names; `#` starts a comment) and
+/// applies only to that directory's own entries, so subdirectories can carry
+/// their own pair.
+///
+/// With a `.dotinclude`, only the entries it matches are installed, even if
+/// `.dotexclude` matches them too. Without one, everything not matched by
+/// `.dotexclude` is. There is no global ignore list, so these files are the
+/// only filter besides the topic metadata file, which is always skipped.
+#[derive(Debug, Default)]
+pub struct DirFilter {
+    include: Option<Vec<String>>,
+    exclude: Vec<String>,
+}
+
+impl DirFilter {
+    pub const INCLUDE_FILE: &'static str = ".dotinclude";
+    pub const EXCLUDE_FILE: &'static str = ".dotexclude";
+
+    pub fn load(dir: &Path) -> Result<Self> {
+        Ok(Self {
+            include: read_patterns(&dir.join(Self::INCLUDE_FILE))?,
+            exclude: read_patterns(&dir.join(Self::EXCLUDE_FILE))?.unwrap_or_default(),
+        })
+    }
+
+    /// Whether the entry called `name` should be installed. The filter files
+    /// themselves never are.
+    pub fn allows(&self, name: &str) -> bool {
+        if name == Self::INCLUDE_FILE || name == Self::EXCLUDE_FILE {
+            return false;
+        }
+        match &self.include {
+            Some(include) => include.iter().any(|pattern| glob_match(pattern, name)),
+            None => !self.exclude.iter().any(|pattern| glob_match(pattern, name)),
+        }
+    }
+}
+
+/// The patterns in a filter file, or `None` if there is no such file.
+fn read_patterns(path: &Path) -> Result<Option<Vec<String>>> {
+    if !path.is_file() {
+        return Ok(None);
+    }
+    let contents =
+        fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    Ok(Some(
+        contents
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(String::from)
+            .collect(),
+    ))
+}
+
+/// Matches `name` against a pattern where `*` stands for any run of
+/// characters and `?` for exactly one.
+pub fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    // Where the last `*` was, and how much of `name` it has swallowed so far
+    let mut star: Option<(usize, usize)> = None;
+
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                // Let the last `*` take one more character and retry
+                Some((star_p, star_n)) => {
+                    star = Some((star_p, star_n + 1));
+                    p = star_p + 1;
+                    n = star_n + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter_dir(name: &str, include: Option<&str>, exclude: Option<&str>) -> DirFilter {
+        let dir =
+            std::env::temp_dir().join(format!("dotfiles-filter-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        if let Some(include) = include {
+            fs::write(dir.join(DirFilter::INCLUDE_FILE), include).unwrap();
+        }
+        if let Some(exclude) = exclude {
+            fs::write(dir.join(DirFilter::EXCLUDE_FILE), exclude).unwrap();
+        }
+        let filter = DirFilter::load(&dir).unwrap();
+        let _ = fs::remove_dir_all(&dir);
+        filter
+    }
+
+    #[test]
+    fn stars_match_any_run_of_characters() {
+        assert!(glob_match("*.lua", "init.lua"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("a*b*c", "aXbYbc"));
+        assert!(glob_match("?.txt", "a.txt"));
+        assert!(!glob_match("?.txt", "ab.txt"));
+        assert!(!glob_match("*.lua", "init.vim"));
+        // Patterns only ever see one entry name, so `**` is just `*`
+        assert!(glob_match("**.lua", "init.lua"));
+        assert_eq!(glob_match("**", "plugins"), glob_match("*", "plugins"));
+    }
+
+    #[test]
+    fn include_file_wins_over_exclude_file() {
+        let filter = filter_dir("precedence", Some("*.lua\n"), Some("# comment\ninit.lua\n"));
+        assert!(filter.allows("init.lua"));
+        assert!(!filter.allows("README.md"));
+        assert!(!filter.allows(DirFilter::INCLUDE_FILE));
+    }
+
+    #[test]
+    fn excluded_entries_are_skipped() {
+        let filter = filter_dir("exclude", None, Some("cache\n*.log\n"));
+        // The names of directories are matched like any other entry
+        assert!(!filter.allows("cache"));
+        assert!(!filter.allows("debug.log"));
+        assert!(filter.allows("init.lua"));
+        assert!(!filter.allows(DirFilter::EXCLUDE_FILE));
+        assert!(DirFilter::default().allows("cache"));
+    }
+}
@@ -0,0 +1,239 @@
+use anyhow::{Context, Result};
+use std::{
+    fs::{self, File},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+/// Writes `contents` to a temp file beside `path`, syncs it, then renames it
+/// over `path` so readers only ever see the old or the new file.
+pub fn write_atomic(path: &Path, contents: &[u8]) -> Result<()> {
+    write_atomic_with_mode(path, contents, None)
+}
+
+/// Like [`write_atomic`], but creates the file with the given Unix
+/// permissions from the start so its content is never readable by others.
+pub fn write_atomic_with_mode(path: &Path, contents: &[u8], mode: Option<u32>) -> Result<()> {
+    let dir = path.parent().context("Path has no parent directory")?;
+    let tmp_path = temp_sibling(path)?;
+
+    let mut open_options = fs::OpenOptions::new();
+    open_options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    if let Some(mode) = mode {
+        use std::os::unix::fs::OpenOptionsExt;
+        open_options.mode(mode);
+    }
+    #[cfg(not(unix))]
+    let _ = mode;
+
+    let written = open_options
+        .open(&tmp_path)
+        .with_context(|| format!("Failed to create {}", tmp_path.display()))
+        .and_then(|mut file| {
+            file.write_all(contents)
+                .with_context(|| format!("Failed to write {}", tmp_path.display()))?;
+            file.sync_all()
+                .with_context(|| format!("Failed to sync {}", tmp_path.display()))
+        })
+        .and_then(|()| {
+            fs::rename(&tmp_path, path)
+                .with_context(|| format!("Failed to move {} into place", path.display()))
+        });
+    if written.is_err() {
+        // Don't leave a half-written temp file next to the untouched original
+        let _ = fs::remove_file(&tmp_path);
+    }
+    written?;
+    sync_dir(dir)
+}
+
+/// Flushes a directory's entries so renames into it survive a crash.
+pub fn sync_dir(dir: &Path) -> Result<()> {
+    #[cfg(unix)]
+    File::open(dir)
+        .and_then(|dir| dir.sync_all())
+        .with_context(|| format!("Failed to sync {}", dir.display()))?;
+
+    #[cfg(not(unix))]
+    let _ = dir;
+
+    Ok(())
+}
+
+/// Creates `dir` and any missing parents with the given Unix permissions
+/// (still subject to the umask). Existing directories are left as they are.
+pub fn create_dir_all_with_mode(dir: &Path, mode: u32) -> Result<()> {
+    let mut builder = fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::DirBuilderExt;
+        builder.mode(mode);
+    }
+    #[cfg(not(unix))]
+    let _ = mode;
+
+    builder
+        .create(dir)
+        .with_context(|| format!("Failed to create directory {}", dir.display()))
+}
+
+pub fn symlink(src: &Path, dest: &Path) -> Result<()> {
+    #[cfg(unix)]
+    std::os::unix::fs::symlink(src, dest).context("Failed to create symlink")?;
+
+    #[cfg(windows)]
+    std::os::windows::fs::symlink_file(src, dest).context("Failed to create symlink")?;
+
+    Ok(())
+}
+
+/// Puts a symlink to `src` at `dest`, replacing whatever is there. The link
+/// is made beside `dest` first, so a failure leaves the original in place.
+pub fn replace_with_symlink(src: &Path, dest: &Path) -> Result<()> {
+    let tmp_path = temp_sibling(dest)?;
+    if fs::symlink_metadata(&tmp_path).is_ok() {
+        fs::remove_file(&tmp_path).context("Failed to remove stale temporary link")?;
+    }
+    symlink(src, &tmp_path)?;
+
+    if fs::symlink_metadata(dest).is_ok_and(|metadata| metadata.is_dir()) {
+        fs::remove_dir_all(dest).context("Failed to remove replaced directory")?;
+    }
+    fs::rename(&tmp_path, dest).context("Failed to move symlink into place")
+}
+
+/// Replaces the symlink at `link` with a copy of the tree at `src`. The copy
+/// is made beside `link` first, so a failed copy leaves the link in place.
+/// A directory can't be renamed over a link, so the link is removed just
+/// before the copy moves in, and put back if that fails.
+pub fn replace_link_with_copy(src: &Path, link: &Path) -> Result<()> {
+    let tmp_path = temp_sibling(link)?;
+    if fs::symlink_metadata(&tmp_path).is_ok() {
+        fs::remove_dir_all(&tmp_path).context("Failed to remove stale temporary copy")?;
+    }
+    if let Err(err) = copy_recursive(src, &tmp_path) {
+        let _ = fs::remove_dir_all(&tmp_path);
+        return Err(err).with_context(|| format!("Failed to copy {}", src.display()));
+    }
+
+    let pointed_at = fs::read_link(link).context("Failed to read link")?;
+    fs::remove_file(link).with_context(|| format!("Failed to remove {}", link.display()))?;
+    if let Err(err) = fs::rename(&tmp_path, link) {
+        let _ = symlink(&pointed_at, link);
+        let _ = fs::remove_dir_all(&tmp_path);
+        return Err(err).with_context(|| format!("Failed to move copy to {}", link.display()));
+    }
+    Ok(())
+}
+
+/// The links in the symlink cycle `path` leads into, in the order they point
+/// at each other, or `None` if following `path` ends somewhere (or nowhere).
+/// Links are followed by hand, since resolving a cycle only fails with an
+/// error that doesn't say which links are involved.
+pub fn symlink_cycle(path: &Path) -> Option<Vec<PathBuf>> {
+    // The kernel gives up after 40 links as well
+    const MAX_HOPS: usize = 40;
+    let mut chain: Vec<PathBuf> = Vec::new();
+    let mut current = normalize(path);
+    for _ in 0..MAX_HOPS {
+        if let Some(start) = chain.iter().position(|seen| *seen == current) {
+            return Some(chain.split_off(start));
+        }
+        let target = fs::read_link(&current).ok()?;
+        let next = match current.parent() {
+            Some(parent) => normalize(&parent.join(target)),
+            None => normalize(&target),
+        };
+        chain.push(current);
+        current = next;
+    }
+    None
+}
+
+/// Whether `child`, an entry of `dir`, is a symlink back to `dir` or one of
+/// its ancestors, so descending into it would never end.
+pub fn loops_back(dir: &Path, child: &Path) -> bool {
+    if !fs::symlink_metadata(child).is_ok_and(|metadata| metadata.file_type().is_symlink()) {
+        return false;
+    }
+    match (fs::canonicalize(dir), fs::canonicalize(child)) {
+        (Ok(dir), Ok(child)) => dir.starts_with(child),
+        _ => false,
+    }
+}
+
+/// Resolves `.` and `..` without touching the filesystem.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            std::path::Component::CurDir => {}
+            std::path::Component::ParentDir => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+/// Copies a file, directory tree or symlink (as a link, not its target).
+pub fn copy_recursive(src: &Path, dest: &Path) -> Result<()> {
+    let metadata = fs::symlink_metadata(src)?;
+
+    if metadata.file_type().is_symlink() {
+        symlink(&fs::read_link(src)?, dest)
+    } else if metadata.is_dir() {
+        fs::create_dir_all(dest)?;
+        for entry in fs::read_dir(src)? {
+            let entry = entry?;
+            copy_recursive(&entry.path(), &dest.join(entry.file_name()))?;
+        }
+        Ok(())
+    } else {
+        fs::copy(src, dest).with_context(|| format!("Failed to copy {}", src.display()))?;
+        Ok(())
+    }
+}
+
+fn temp_sibling(path: &Path) -> Result<PathBuf> {
+    let dir = path.parent().context("Path has no parent directory")?;
+    let file_name = path.file_name().context("Invalid file name")?;
+    Ok(dir.join(format!(".{}.tmp", file_name.to_string_lossy())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn failed_rename_removes_temp_file() {
+        let dir = std::env::temp_dir().join(format!("dotfiles-fsutil-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        // A non-empty directory can't be renamed over
+        let path = dir.join("config.toml");
+        fs::create_dir_all(path.join("keep")).unwrap();
+
+        assert!(write_atomic(&path, b"new").is_err());
+        assert!(path.join("keep").is_dir());
+        assert!(fs::symlink_metadata(temp_sibling(&path).unwrap()).is_err());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn failed_copy_leaves_the_link() {
+        let dir = std::env::temp_dir().join(format!("dotfiles-fsutil-copy-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let link = dir.join("lua");
+        symlink(&dir.join("missing"), &link).unwrap();
+
+        assert!(replace_link_with_copy(&dir.join("missing"), &link).is_err());
+        assert_eq!(fs::read_link(&link).unwrap(), dir.join("missing"));
+        assert!(fs::symlink_metadata(dir.join(".lua.tmp")).is_err());
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
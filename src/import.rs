@@ -0,0 +1,166 @@
+use crate::config;
+use anyhow::{Context, Result};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Layout of a dotfiles directory managed by another tool.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Layout {
+    /// GNU Stow: one directory per package, each mirroring `$HOME`.
+    #[default]
+    Stow,
+    /// chezmoi: names like `dot_config` and `private_dot_ssh`.
+    Chezmoi,
+}
+
+/// chezmoi name prefixes that only carry attributes.
+const CHEZMOI_ATTRIBUTES: &[&str] = &[
+    "private_",
+    "readonly_",
+    "executable_",
+    "empty_",
+    "exact_",
+    "create_",
+];
+
+/// Builds a config mapping everything in `dir` to where the other tool would
+/// put it. Sources are relative to `dir`, which is meant to become the
+/// dotfiles repo. Entries that have no equivalent here are returned as
+/// warnings instead.
+pub fn import(dir: &Path, layout: Layout) -> Result<(toml_edit::DocumentMut, Vec<String>)> {
+    let mut doc = toml_edit::DocumentMut::new();
+    let mut warnings = Vec::new();
+    match layout {
+        Layout::Stow => import_stow(dir, &mut doc)?,
+        Layout::Chezmoi => {
+            let mut files = Vec::new();
+            collect_chezmoi(dir, dir, &mut files, &mut warnings)?;
+            files.sort();
+            for (source, target) in files {
+                config::add_mapping(&mut doc, &source, &target)?;
+            }
+        }
+    }
+    Ok((doc, warnings))
+}
+
+/// Maps each top-level entry of every package, so `zsh/.zshrc` lands at
+/// `~/.zshrc`. Directories several packages share, like `.config`, are
+/// split into their entries until each mapping has a target of its own.
+fn import_stow(dir: &Path, doc: &mut toml_edit::DocumentMut) -> Result<()> {
+    let mut packages = Vec::new();
+    for package in sorted_entries(dir)? {
+        let name = file_name(&package);
+        if package.is_dir() && !name.starts_with('.') {
+            packages.push(name);
+        }
+    }
+    let shared = |relative: &Path| {
+        packages
+            .iter()
+            .filter(|package| dir.join(package).join(relative).is_dir())
+            .count()
+            > 1
+    };
+
+    // (package, path within the package)
+    let mut pending = Vec::new();
+    for package in &packages {
+        for entry in sorted_entries(&dir.join(package))? {
+            pending.push((package.clone(), PathBuf::from(file_name(&entry))));
+        }
+    }
+    let mut mappings = Vec::new();
+    while let Some((package, relative)) = pending.pop() {
+        let source = dir.join(&package).join(&relative);
+        if source.is_dir() && shared(&relative) {
+            for entry in sorted_entries(&source)? {
+                pending.push((package.clone(), relative.join(file_name(&entry))));
+            }
+        } else {
+            mappings.push((package, relative));
+        }
+    }
+
+    mappings.sort();
+    for (package, relative) in mappings {
+        let relative = relative.to_string_lossy().replace('\\', "/");
+        config::add_mapping(doc, &format!("{}/{}", package, relative), &relative)?;
+    }
+    Ok(())
+}
+
+/// Maps every file below `dir` individually, since each directory level may
+/// carry its own chezmoi prefixes.
+fn collect_chezmoi(
+    root: &Path,
+    dir: &Path,
+    files: &mut Vec<(String, String)>,
+    warnings: &mut Vec<String>,
+) -> Result<()> {
+    for entry in sorted_entries(dir)? {
+        let name = file_name(&entry);
+        if name.starts_with('.') {
+            // .chezmoiignore, .chezmoi.toml.tmpl, .git and friends
+            continue;
+        }
+        let relative = entry.strip_prefix(root).unwrap_or(&entry);
+        if name.starts_with("run_")
+            || name.starts_with("modify_")
+            || name.starts_with("symlink_")
+            || name.starts_with("encrypted_")
+            || name.ends_with(".tmpl")
+        {
+            warnings.push(format!("Skipped {} (not supported)", relative.display()));
+            continue;
+        }
+        if entry.is_dir() {
+            collect_chezmoi(root, &entry, files, warnings)?;
+            continue;
+        }
+
+        let target: Vec<String> = relative
+            .iter()
+            .map(|part| chezmoi_name(&part.to_string_lossy()))
+            .collect();
+        files.push((
+            relative.to_string_lossy().replace('\\', "/"),
+            target.join("/"),
+        ));
+    }
+    Ok(())
+}
+
+/// The real name behind a chezmoi source name: attribute prefixes dropped
+/// and `dot_` turned into a leading `.`.
+fn chezmoi_name(name: &str) -> String {
+    let mut name = name;
+    while let Some(rest) = CHEZMOI_ATTRIBUTES
+        .iter()
+        .find_map(|prefix| name.strip_prefix(prefix))
+    {
+        name = rest;
+    }
+    let name = name.strip_prefix("literal_").unwrap_or(name);
+    match name.strip_prefix("dot_") {
+        Some(rest) => format!(".{}", rest),
+        None => name.to_string(),
+    }
+}
+
+fn sorted_entries(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut entries = fs::read_dir(dir)
+        .with_context(|| format!("Failed to read {}", dir.display()))?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+    entries.sort();
+    Ok(entries)
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
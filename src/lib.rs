@@ -1 +1,12 @@
-mod dotfile_manager;
+pub mod config;
+pub mod diff;
+pub mod dotfile_manager;
+pub mod events;
+pub mod filter;
+pub mod fsutil;
+pub mod import;
+pub mod report;
+pub mod state;
+pub mod status;
+pub mod template;
+pub mod transaction;
@@ -1,24 +1,28 @@
 use anyhow::{Context, Result};
-use clap::{Parser, Subcommand};
-use serde::Deserialize;
+use clap::{CommandFactory, Parser, Subcommand};
+use clap_complete::Shell;
+use dotfiles::{
+    config,
+    diff::DiffFormat,
+    dotfile_manager::{BackupLayout, BackupStrategy, DotfileManager, Options},
+    import,
+    report::ReportFormat,
+};
 use std::{
+    env,
+    ffi::OsString,
     fs,
+    io::IsTerminal,
     path::{Path, PathBuf},
+    time::Duration,
 };
 
-mod dotfile_manager;
-use dotfile_manager::DotfileManager;
-
-#[derive(Parser, Debug)]
-#[command(author, version, about, long_about = None)]
-struct Args {
-    /// Command to execute (install, list, check)
-    #[arg(default_value = "install")]
-    command: String,
-
-    /// Verbose output
-    #[arg(short, long)]
-    verbose: bool,
+#[derive(Clone, Copy, Default, clap::ValueEnum)]
+enum ColorChoice {
+    #[default]
+    Auto,
+    Always,
+    Never,
 }
 
 #[derive(Parser)]
@@ -30,46 +34,938 @@ struct Cli {
     /// Enable verbose output
     #[arg(short, long)]
     verbose: bool,
+
+    /// Path to the config file (searched before the standard locations)
+    #[arg(short, long)]
+    config: Option<PathBuf>,
+
+    /// When to color output: auto colors only when writing to a terminal
+    #[arg(long, value_enum, value_name = "WHEN", default_value_t)]
+    color: ColorChoice,
+
+    /// Machine name to use instead of the hostname (overrides $DOTFILES_MACHINE)
+    #[arg(long)]
+    machine: Option<String>,
+
+    /// Don't resolve symlinks in the dotfiles directory path
+    #[arg(long)]
+    no_canonicalize: bool,
+
+    /// Install into this directory instead of the real home directory
+    #[arg(long)]
+    home: Option<PathBuf>,
+
+    /// Location of the dotfiles repo (defaults to ~/dotfiles)
+    #[arg(long)]
+    dotfiles_dir: Option<PathBuf>,
+
+    /// Allow mapping targets that resolve outside the home directory
+    #[arg(long)]
+    allow_outside_home: bool,
+
+    /// Stop walking directory sources this many subdirectory levels down,
+    /// linking deeper directories whole (mappings can set max_depth)
+    #[arg(long, value_name = "N")]
+    max_depth: Option<usize>,
+
+    /// Select mappings for this OS instead of the one we're running on
+    #[arg(long, value_parser = clap::builder::PossibleValuesParser::new(config::KNOWN_OS))]
+    os: Option<String>,
+
+    /// Skip mappings whose `os` list names this OS, even when it also names
+    /// the selected one (repeatable). Mappings without an `os` list are
+    /// never skipped, and this doesn't change the selected OS
+    #[arg(long, value_name = "OS", value_parser = clap::builder::PossibleValuesParser::new(config::KNOWN_OS))]
+    exclude_os: Vec<String>,
+
+    /// Install into this user's home (from /etc/passwd), giving them the
+    /// created files when run as root
+    #[arg(long, value_name = "NAME", conflicts_with = "home")]
+    user: Option<String>,
+
+    /// Don't check for missing secrets (also `[secrets] enabled = false`)
+    #[arg(long)]
+    no_secrets_check: bool,
+
+    /// Copy this directory mapping's files, keeping its structure and modes,
+    /// instead of linking them, as if it set `strategy = "copy"`. Status then
+    /// compares contents. Repeatable
+    #[arg(long, value_name = "SOURCE")]
+    copy_dir: Vec<String>,
+
+    /// Map SOURCE (in the dotfiles repo) to TARGET (under home) instead of
+    /// reading a config file. Repeatable
+    #[arg(long = "mapping", value_name = "SOURCE:TARGET", value_parser = parse_mapping)]
+    mappings: Vec<(String, String)>,
+
+    /// Put TOPIC's files in DIR (relative to home) when installing topics
+    /// directly, overriding the built-in nvim and zellij locations.
+    /// Repeatable
+    #[arg(long = "topic-target", value_name = "TOPIC=DIR", value_parser = parse_topic_target)]
+    topic_targets: Vec<(String, String)>,
+
+    /// Fail on config keys nothing reads, like a misspelt `targt`, instead
+    /// of warning about them and carrying on
+    #[arg(long)]
+    strict_config: bool,
+
+    /// Exit non-zero if any warning was printed: a missing secret, an empty
+    /// source directory, a step that failed to roll back, backups over
+    /// --backup-warn-size, a skipped import-stow entry and the like. Mappings
+    /// left out for another OS or disabled are expected and don't count
+    #[arg(long)]
+    fail_on_warning: bool,
+
+    /// Print full paths instead of ~/ and repo-relative ones
+    #[arg(long)]
+    absolute: bool,
+
+    /// Parse the config as this format instead of guessing from its extension
+    /// (also $DOTFILES_CONFIG_FORMAT)
+    #[arg(long, value_enum, value_name = "FORMAT")]
+    format_in: Option<config::ConfigFormat>,
+
+    /// Override a config value for this run, e.g. defaults.strategy=copy or
+    /// files.0.strategy=copy for the first mapping (repeatable)
+    #[arg(
+        long = "set",
+        visible_alias = "config-override",
+        value_name = "KEY=VALUE"
+    )]
+    overrides: Vec<String>,
 }
 
 #[derive(Subcommand)]
 enum Commands {
     /// Install all dotfiles
-    Install,
+    Install {
+        /// Only install mappings for this topic (first segment of the source path)
+        topic: Option<String>,
+
+        /// Stream install events as JSON lines instead of normal output
+        #[arg(long)]
+        events: bool,
+
+        /// Fail if a target's parent directory does not already exist
+        #[arg(long)]
+        no_create_dirs: bool,
+
+        /// Print how long each mapping took, plus the total
+        #[arg(long)]
+        time: bool,
+
+        /// How to save existing targets before replacing them
+        #[arg(long, value_enum, default_value_t)]
+        backup_strategy: BackupStrategy,
+
+        /// Where backups go inside the backup directory
+        #[arg(long, value_enum, default_value_t)]
+        backup_layout: BackupLayout,
+
+        /// Show what would be done without changing anything
+        #[arg(long)]
+        dry_run: bool,
+
+        /// Replace existing targets without backing them up
+        #[arg(long)]
+        no_backup: bool,
+
+        /// Octal permissions for new backup directories
+        #[arg(long, value_parser = parse_mode, default_value = "700")]
+        backup_mode: u32,
+
+        /// Append a record of every change to this file
+        /// [default: ~/.dotfiles_backup/audit.log]
+        #[arg(long, value_name = "PATH")]
+        audit_log: Option<PathBuf>,
+
+        /// Fail if a required secret is missing instead of warning
+        #[arg(long)]
+        strict_secrets: bool,
+
+        /// Copy existing targets into a new backup without linking anything
+        #[arg(long, conflicts_with = "only_missing")]
+        backup_only: bool,
+
+        /// Only install mappings with missing or broken targets, leaving the rest untouched
+        #[arg(long)]
+        only_missing: bool,
+
+        /// Undo every change if any mapping fails (always keeps backups)
+        #[arg(long)]
+        transactional: bool,
+
+        /// Check afterwards that every new link resolves to its source
+        #[arg(long)]
+        verify: bool,
+
+        /// Print only the backup directory this run uses (with --dry-run,
+        /// print it and stop)
+        #[arg(long, conflicts_with = "events")]
+        print_backup_dir: bool,
+
+        /// Show each mapping's planned changes and ask before applying them
+        #[arg(short, long, conflicts_with_all = ["dry_run", "events", "print_backup_dir"])]
+        interactive: bool,
+
+        /// Don't ask, even with --interactive
+        #[arg(short, long)]
+        yes: bool,
+
+        /// Keep running and reinstall whenever the repo or config changes
+        #[arg(long, conflicts_with_all = ["interactive", "print_backup_dir"])]
+        watch: bool,
+
+        /// With --watch, how long to wait after the last change before
+        /// reinstalling
+        #[arg(long, value_name = "MS", default_value_t = 300, requires = "watch")]
+        debounce: u64,
+
+        /// With --watch, don't reinstall for changes to paths matching this
+        /// glob (repo-relative path or file name; repeatable)
+        #[arg(long, value_name = "GLOB", requires = "watch")]
+        watch_ignore: Vec<String>,
+
+        /// Warn when ~/.dotfiles_backup grows past this size, e.g. 500MB or 2GB
+        #[arg(long, value_name = "SIZE", value_parser = parse_size, default_value = "500MB")]
+        backup_warn_size: u64,
+
+        /// Install the config and sources as they were at this git ref,
+        /// exported to a temporary directory; the working tree is untouched
+        #[arg(long, value_name = "REF")]
+        config_from_git: Option<String>,
+
+        /// Write a summary of the run to this file
+        #[arg(long, value_name = "PATH")]
+        report: Option<PathBuf>,
+
+        /// Write a shell script with the rm/mv commands that undo this run.
+        /// Targets replaced without a backup are listed in it as comments,
+        /// since nothing can bring them back
+        #[arg(long, value_name = "PATH")]
+        reverse_script: Option<PathBuf>,
+
+        /// Format of the --report file
+        #[arg(long, value_enum, default_value_t)]
+        report_format: ReportFormat,
+
+        /// Hide each mapping's output unless that mapping fails; warnings
+        /// are still shown
+        #[arg(long)]
+        output_on_error: bool,
+    },
 
     /// List available configurations
-    List,
+    List {
+        /// Group mappings under the directories their targets go in
+        #[arg(long)]
+        tree: bool,
+    },
+
+    /// Show why a mapping is or isn't installed: each filter's result, the
+    /// source found and where its targets resolve
+    Explain {
+        /// The mapping's source or name
+        source: String,
+
+        /// Print the explanation as JSON
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// List topics with how many of their files are installed
+    Topics,
 
     /// Check configuration status
-    Status,
+    Status {
+        /// Print a single word: ok, drift or missing
+        #[arg(long, conflicts_with = "json")]
+        short: bool,
+
+        /// Print installed/missing/drifted counts as JSON
+        #[arg(long)]
+        json: bool,
+
+        /// Compare file contents in --short/--json mode, and check the repo
+        /// contents behind directory links file by file (slower)
+        #[arg(long)]
+        deep: bool,
+
+        /// Offer a fix for each target that isn't installed
+        #[arg(long, conflicts_with_all = ["short", "json"])]
+        fix: bool,
+
+        /// With --fix, apply every safe fix without asking
+        #[arg(short, long, requires = "fix")]
+        yes: bool,
+
+        /// Show sources modified and targets drifted since the last install
+        #[arg(long, conflicts_with_all = ["short", "json", "fix"])]
+        since_install: bool,
+
+        /// Keep redrawing the status, on every change to the repo and at
+        /// least every --interval seconds
+        #[arg(long, conflicts_with_all = ["short", "json", "fix", "since_install"])]
+        watch: bool,
+
+        /// With --watch, seconds between redraws
+        #[arg(long, value_name = "SECS", default_value_t = 5, requires = "watch")]
+        interval: u64,
+    },
+
+    /// Replace targets identical to their source with links
+    Adopt,
+
+    /// Delete old backups
+    #[command(visible_alias = "prune-backups")]
+    Clean {
+        /// Always keep this many of the newest backups
+        #[arg(long)]
+        keep: Option<usize>,
+
+        /// Only remove backups older than this, e.g. 30d, 12h or 2w
+        #[arg(long, value_parser = parse_age)]
+        older_than: Option<chrono::Duration>,
+
+        /// Show what would be removed and how much space it frees
+        #[arg(long)]
+        dry_run: bool,
+    },
+
+    /// Compress old backups into .tar.gz archives; restore still reads them
+    ArchiveBackups {
+        /// Archive backups older than this, e.g. 30d, 12h or 2w
+        #[arg(long, value_parser = parse_age)]
+        older_than: chrono::Duration,
+
+        /// Show what would be archived without touching anything
+        #[arg(long)]
+        dry_run: bool,
+    },
+
+    /// List backup runs and how much space they take
+    Backups {
+        /// Print only the total size
+        #[arg(long)]
+        size: bool,
+    },
+
+    /// Pull the dotfiles repo (fast-forward only) and reinstall
+    Sync {
+        /// Fetch and show incoming commits and the install plan for what they
+        /// change, without pulling or installing
+        #[arg(long)]
+        dry_run: bool,
+    },
+
+    /// Remove empty directories that install created
+    Gc {
+        /// Show which directories would be removed
+        #[arg(long)]
+        dry_run: bool,
+    },
+
+    /// Put back the files saved in a backup (the latest one by default)
+    Restore {
+        /// Backup directory name under ~/.dotfiles_backup, e.g. 20240101_120000
+        backup: Option<String>,
+    },
+
+    /// Verify recorded links, backups and sources are intact
+    Verify,
+
+    /// Show how targets differ from what install would put there
+    Diff {
+        /// Only diff mappings in this topic
+        topic: Option<String>,
+
+        /// How to render the differences
+        #[arg(long, value_enum, default_value_t, visible_alias = "output-format")]
+        diff_format: DiffFormat,
+    },
+
+    /// Write a Graphviz DOT graph of mappings and their targets
+    Graph {
+        /// File to write the graph to (defaults to stdout)
+        #[arg(long)]
+        out: Option<PathBuf>,
+    },
+
+    /// Install into a temporary home and check every target
+    SelfTest,
+
+    /// Re-enable a disabled mapping
+    Enable {
+        /// Source of the mapping, as written in the config
+        name: String,
+    },
+
+    /// Disable a mapping without removing it from the config
+    Disable {
+        /// Source of the mapping, as written in the config
+        name: String,
+    },
 
     /// Add a new configuration file
     Add {
-        /// Topic (e.g., vim, zsh)
-        topic: String,
+        /// Topic (e.g., vim, zsh); with --interactive, just the file
+        #[arg(required_unless_present = "batch")]
+        topic: Option<String>,
         /// Path to the file to add
-        file: PathBuf,
+        file: Option<PathBuf>,
+
+        /// Prompt for the topic and target and record the mapping in the config
+        #[arg(short, long, conflicts_with = "move_file")]
+        interactive: bool,
+
+        /// Move the file into the repo, map it and link it back in its place
+        #[arg(long = "move")]
+        move_file: bool,
+
+        /// Add every path listed in this file (one per line, # comments) and
+        /// map each to where it was found, in one config write
+        #[arg(long, value_name = "LIST", conflicts_with_all = ["interactive", "move_file", "file"])]
+        batch: Option<PathBuf>,
+
+        /// Topic for --batch, instead of giving it positionally
+        #[arg(long = "topic", value_name = "TOPIC", requires = "batch")]
+        batch_topic: Option<String>,
+    },
+
+    /// Delete a file from a topic, its mappings and the links to it
+    Remove {
+        /// Topic the file is in
+        topic: String,
+        /// Name of the file within the topic
+        file: String,
+
+        /// Keep the topic directory and its [topics.*] table even once empty
+        #[arg(long)]
+        keep_empty: bool,
+
+        /// Replace linked targets with a copy of the file instead of
+        /// unlinking them
+        #[arg(long)]
+        keep_target: bool,
+    },
+
+    /// Print a config mapping a GNU Stow (or chezmoi) directory, for review
+    ImportStow {
+        /// The stow directory, holding one directory per package
+        dir: PathBuf,
+
+        /// Layout of the directory
+        #[arg(long, value_enum, default_value_t)]
+        from: import::Layout,
+    },
+
+    /// Print the config as the tool sees it after defaults, OS selection and
+    /// template expansion, without installing anything
+    DumpConfig {
+        /// Output syntax
+        #[arg(long, value_enum, default_value_t)]
+        format: config::ConfigFormat,
+    },
+
+    /// Print a JSON Schema for config.toml, for editor integration
+    Schema,
+
+    /// Print version information
+    Version {
+        /// Also print the git commit, rustc version and config schema version
+        #[arg(long)]
+        verbose: bool,
+    },
+
+    /// Print a shell completion script
+    #[command(args_conflicts_with_subcommands = true)]
+    Completions {
+        /// Shell to generate completions for
+        shell: Option<Shell>,
+
+        #[command(subcommand)]
+        command: Option<CompletionsCommand>,
+    },
+}
+
+impl Commands {
+    /// Whether the command changes links, backups or the config, and so must
+    /// not overlap with another run.
+    fn mutates(&self) -> bool {
+        match self {
+            Commands::Install { dry_run, .. } => !dry_run,
+            Commands::Status { fix, .. } => *fix,
+            Commands::Clean { dry_run, .. }
+            | Commands::ArchiveBackups { dry_run, .. }
+            | Commands::Gc { dry_run }
+            | Commands::Sync { dry_run } => !dry_run,
+            Commands::Adopt
+            | Commands::Restore { .. }
+            | Commands::Enable { .. }
+            | Commands::Disable { .. }
+            | Commands::Add { .. }
+            | Commands::Remove { .. } => true,
+            Commands::List { .. }
+            | Commands::DumpConfig { .. }
+            | Commands::Topics
+            | Commands::Explain { .. }
+            | Commands::Verify
+            | Commands::Diff { .. }
+            | Commands::Backups { .. }
+            | Commands::Graph { .. }
+            | Commands::SelfTest
+            | Commands::Version { .. }
+            | Commands::Schema
+            | Commands::ImportStow { .. }
+            | Commands::Completions { .. } => false,
+        }
+    }
+}
+
+#[derive(Subcommand)]
+enum CompletionsCommand {
+    /// Write the completion script to the shell's user completion directory
+    Install {
+        /// Shell to install for (detected from $SHELL by default)
+        #[arg(long)]
+        shell: Option<Shell>,
     },
 }
 
+/// The per-user directory each shell loads completions from, and the file
+/// name it expects.
+fn completion_path(shell: Shell, home: &Path) -> Result<PathBuf> {
+    let xdg = |var: &str, fallback: &str| {
+        env::var_os(var)
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .unwrap_or_else(|| home.join(fallback))
+    };
+    Ok(match shell {
+        Shell::Bash => xdg("XDG_DATA_HOME", ".local/share")
+            .join("bash-completion/completions")
+            .join("dotfiles"),
+        Shell::Zsh => home.join(".zfunc").join("_dotfiles"),
+        Shell::Fish => xdg("XDG_CONFIG_HOME", ".config")
+            .join("fish/completions")
+            .join("dotfiles.fish"),
+        _ => anyhow::bail!(
+            "Don't know where {} loads completions from; use `dotfiles completions {}` instead",
+            shell,
+            shell
+        ),
+    })
+}
+
+fn install_completions(shell: Option<Shell>, home: Option<PathBuf>) -> Result<()> {
+    let shell = match shell {
+        Some(shell) => shell,
+        None => Shell::from_env().context("Could not detect shell from $SHELL; pass --shell")?,
+    };
+    let home = match home {
+        Some(home) => home,
+        None => dirs::home_dir().context("Could not determine home directory")?,
+    };
+    let path = completion_path(shell, &home)?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create {}", parent.display()))?;
+    }
+
+    let mut script = Vec::new();
+    clap_complete::generate(shell, &mut Cli::command(), "dotfiles", &mut script);
+    fs::write(&path, script).with_context(|| format!("Failed to write {}", path.display()))?;
+
+    println!("Installed {} completions to {}", shell, path.display());
+    if shell == Shell::Zsh {
+        println!("Make sure ~/.zfunc is in your fpath before compinit runs");
+    }
+    Ok(())
+}
+
+fn parse_age(value: &str) -> Result<chrono::Duration, String> {
+    let split = value.len() - value.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    let (amount, unit) = value.split_at(split);
+    let amount: i64 = amount
+        .parse()
+        .map_err(|_| format!("`{}` should look like 30d, 12h or 2w", value))?;
+    match unit {
+        "m" => Ok(chrono::Duration::minutes(amount)),
+        "h" => Ok(chrono::Duration::hours(amount)),
+        "d" => Ok(chrono::Duration::days(amount)),
+        "w" => Ok(chrono::Duration::weeks(amount)),
+        _ => Err(format!("`{}` should end in m, h, d or w", value)),
+    }
+}
+
+fn parse_mapping(value: &str) -> Result<(String, String), String> {
+    match value.split_once(':') {
+        Some((source, target)) if !source.is_empty() && !target.is_empty() => {
+            Ok((source.to_string(), target.to_string()))
+        }
+        _ => Err(format!("`{}` should look like SOURCE:TARGET", value)),
+    }
+}
+
+fn parse_topic_target(value: &str) -> Result<(String, String), String> {
+    match value.split_once('=') {
+        Some((topic, dir)) if !topic.is_empty() && !dir.is_empty() => {
+            Ok((topic.to_string(), dir.to_string()))
+        }
+        _ => Err(format!("`{}` should look like TOPIC=DIR", value)),
+    }
+}
+
+fn parse_size(value: &str) -> Result<u64, String> {
+    let split = value.len() - value.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    let (amount, unit) = value.split_at(split);
+    let amount: u64 = amount
+        .parse()
+        .map_err(|_| format!("`{}` should look like 500MB or 2GB", value))?;
+    let multiplier: u64 = match unit.to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "KB" | "K" => 1 << 10,
+        "MB" | "M" => 1 << 20,
+        "GB" | "G" => 1 << 30,
+        _ => return Err(format!("`{}` should end in B, KB, MB or GB", value)),
+    };
+    Ok(amount * multiplier)
+}
+
+fn parse_mode(value: &str) -> Result<u32, String> {
+    u32::from_str_radix(value.trim_start_matches("0o"), 8)
+        .map_err(|_| format!("`{}` is not an octal mode", value))
+}
+
+/// Rewrites a `--dump-config` flag given before the subcommand into the
+/// `dump-config` subcommand, so `dotfiles --dump-config --format json` works.
+fn forward_dump_config(mut args: Vec<OsString>) -> Vec<OsString> {
+    let command = Cli::command();
+    let is_subcommand = |arg: &OsString| {
+        arg.to_str()
+            .is_some_and(|name| command.find_subcommand(name).is_some())
+    };
+    let flag = args
+        .iter()
+        .take_while(|arg| !is_subcommand(arg) && *arg != "--")
+        .position(|arg| arg == "--dump-config");
+    if let Some(flag) = flag {
+        args[flag] = "dump-config".into();
+    }
+    args
+}
+
 fn main() -> Result<()> {
-    let cli = Cli::parse();
-    let manager = DotfileManager::new(cli.verbose)?;
+    let cli = Cli::parse_from(forward_dump_config(env::args_os().collect()));
+    match cli.color {
+        ColorChoice::Auto => {}
+        ColorChoice::Always => colored::control::set_override(true),
+        ColorChoice::Never => colored::control::set_override(false),
+    }
+    if let Commands::Schema = cli.command {
+        let schema = schemars::schema_for!(config::Config);
+        println!("{}", serde_json::to_string_pretty(&schema)?);
+        return Ok(());
+    }
+    if let Commands::ImportStow { dir, from } = &cli.command {
+        let (doc, warnings) = import::import(dir, *from)?;
+        for warning in &warnings {
+            eprintln!("{}", warning);
+        }
+        print!("{}", doc);
+        if cli.fail_on_warning && !warnings.is_empty() {
+            anyhow::bail!("{} warning(s) printed (--fail-on-warning)", warnings.len());
+        }
+        return Ok(());
+    }
+    if let Commands::Version { verbose } = cli.command {
+        println!("dotfiles {}", env!("CARGO_PKG_VERSION"));
+        if verbose {
+            println!("commit: {}", env!("GIT_HASH"));
+            println!("rustc: {}", env!("RUSTC_VERSION"));
+            println!("config schema: {}", config::SCHEMA_VERSION);
+        }
+        return Ok(());
+    }
+    if let Commands::Completions { shell, command } = cli.command {
+        return match (command, shell) {
+            (Some(CompletionsCommand::Install { shell }), _) => {
+                install_completions(shell, cli.home)
+            }
+            (None, Some(shell)) => {
+                clap_complete::generate(
+                    shell,
+                    &mut Cli::command(),
+                    "dotfiles",
+                    &mut std::io::stdout(),
+                );
+                Ok(())
+            }
+            (None, None) => anyhow::bail!("Pass a shell, or `install` to set completions up"),
+        };
+    }
+
+    let mut options = Options {
+        verbose: cli.verbose,
+        config: cli.config,
+        machine: cli.machine,
+        no_canonicalize: cli.no_canonicalize,
+        home: cli.home,
+        dotfiles_dir: cli.dotfiles_dir,
+        allow_outside_home: cli.allow_outside_home,
+        max_depth: cli.max_depth,
+        os: cli.os,
+        exclude_os: cli.exclude_os,
+        absolute: cli.absolute,
+        no_secrets_check: cli.no_secrets_check,
+        user: cli.user,
+        copy_dirs: cli.copy_dir,
+        mappings: cli.mappings,
+        topic_targets: cli.topic_targets.into_iter().collect(),
+        strict_config: cli.strict_config,
+        overrides: cli.overrides,
+        config_format: cli.format_in,
+        ..Default::default()
+    };
+    if let Commands::Install {
+        events,
+        no_create_dirs,
+        time,
+        backup_strategy,
+        backup_layout,
+        dry_run,
+        no_backup,
+        backup_mode,
+        audit_log,
+        strict_secrets,
+        report,
+        report_format,
+        output_on_error,
+        only_missing,
+        backup_only,
+        transactional,
+        verify,
+        print_backup_dir,
+        interactive,
+        yes,
+        backup_warn_size,
+        config_from_git,
+        reverse_script,
+        ..
+    } = &cli.command
+    {
+        options.reverse_script = reverse_script.clone();
+        options.config_from_git = config_from_git.clone();
+        options.backup_warn_size = Some(*backup_warn_size);
+        // Prompts need someone to answer them
+        options.interactive = *interactive && !*yes && std::io::stdin().is_terminal();
+        options.backup_mode = Some(*backup_mode);
+        options.no_backup = *no_backup;
+        options.audit_log = audit_log.clone();
+        options.strict_secrets = *strict_secrets;
+        options.report = report.clone();
+        options.only_missing = *only_missing;
+        options.backup_only = *backup_only;
+        options.transactional = *transactional;
+        options.verify_links = *verify;
+        options.print_backup_dir = *print_backup_dir;
+        options.report_format = *report_format;
+        options.output_on_error = *output_on_error;
+        options.dry_run = *dry_run;
+        options.backup_strategy = *backup_strategy;
+        options.backup_layout = *backup_layout;
+        options.events = *events;
+        options.no_create_dirs = *no_create_dirs;
+        options.time = *time;
+    }
+    let manager = DotfileManager::new(options)?;
+    let _lock = if cli.command.mutates() {
+        Some(manager.lock()?)
+    } else {
+        None
+    };
 
+    let fail_on_warning = cli.fail_on_warning;
     match cli.command {
-        Commands::Install => {
-            manager.install()?;
+        Commands::Install {
+            topic,
+            watch,
+            debounce,
+            watch_ignore,
+            ..
+        } => {
+            manager.install(topic.as_deref())?;
+            if watch {
+                manager.watch(
+                    topic.as_deref(),
+                    Duration::from_millis(debounce),
+                    &watch_ignore,
+                )?;
+            }
         }
-        Commands::List => {
+        Commands::List { tree: false } => {
             manager.list_configs()?;
         }
-        Commands::Status => {
-            manager.check_status()?;
+        Commands::List { tree: true } => {
+            manager.list_tree()?;
+        }
+        Commands::Status {
+            short,
+            json,
+            deep,
+            fix,
+            yes,
+            since_install,
+            watch,
+            interval,
+        } => {
+            if watch {
+                manager.watch_status(Duration::from_secs(interval), deep)?;
+            } else if since_install {
+                manager.changes_since_install()?;
+            } else if fix {
+                manager.fix(yes)?;
+            } else if short {
+                println!("{}", manager.status_summary(deep)?.token());
+            } else if json {
+                println!("{}", serde_json::to_string(&manager.status_summary(deep)?)?);
+            } else {
+                manager.check_status(deep)?;
+            }
+        }
+        Commands::Adopt => {
+            manager.adopt()?;
         }
-        Commands::Add { topic, file } => {
+        Commands::Clean {
+            keep,
+            older_than,
+            dry_run,
+        } => {
+            manager.clean(keep, older_than, dry_run)?;
+        }
+        Commands::ArchiveBackups {
+            older_than,
+            dry_run,
+        } => {
+            manager.archive_backups(older_than, dry_run)?;
+        }
+        Commands::Backups { size } => {
+            manager.list_backups(size)?;
+        }
+        Commands::Sync { dry_run } => {
+            manager.sync(dry_run)?;
+        }
+        Commands::Gc { dry_run } => {
+            manager.gc(dry_run)?;
+        }
+        Commands::Restore { backup } => {
+            manager.restore(backup.as_deref())?;
+        }
+        Commands::DumpConfig { format } => {
+            print!("{}", format.render(&manager.effective_config()?)?);
+        }
+        Commands::Topics => {
+            manager.list_topics()?;
+        }
+        Commands::Explain { source, json } => {
+            let explanations = manager.explain(&source)?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&explanations)?);
+            } else {
+                for explanation in &explanations {
+                    explanation.print();
+                }
+            }
+        }
+        Commands::Verify => {
+            manager.verify()?;
+        }
+        Commands::Diff { topic, diff_format } => {
+            manager.diff(topic.as_deref(), diff_format)?;
+        }
+        Commands::Graph { out } => {
+            let dot = manager.graph()?;
+            match out {
+                Some(path) => fs::write(&path, dot)
+                    .with_context(|| format!("Failed to write {}", path.display()))?,
+                None => print!("{}", dot),
+            }
+        }
+        Commands::SelfTest => {
+            manager.self_test()?;
+        }
+        Commands::Enable { name } => {
+            manager.set_enabled(&name, true)?;
+        }
+        Commands::Disable { name } => {
+            manager.set_enabled(&name, false)?;
+        }
+        Commands::Add {
+            topic,
+            batch: Some(list),
+            batch_topic,
+            ..
+        } => {
+            let topic = batch_topic
+                .or(topic)
+                .context("--batch needs a topic, e.g. --topic shell")?;
+            manager.add_batch(&topic, &list)?;
+        }
+        Commands::Add {
+            topic: Some(topic),
+            file: Some(file),
+            interactive: true,
+            ..
+        } => {
+            manager.add_interactive(&file, Some(&topic))?;
+        }
+        Commands::Add {
+            topic: Some(file),
+            file: None,
+            interactive: true,
+            ..
+        } => {
+            manager.add_interactive(file.as_ref(), None)?;
+        }
+        Commands::Add {
+            topic: Some(topic),
+            file: Some(file),
+            move_file: true,
+            ..
+        } => {
+            manager.add_moved(&topic, &file)?;
+        }
+        Commands::Add {
+            topic: Some(topic),
+            file: Some(file),
+            ..
+        } => {
             manager.add_config(&topic, &file)?;
         }
+        Commands::Add { .. } => {
+            anyhow::bail!("add needs both a topic and a file unless --interactive is given");
+        }
+        Commands::Remove {
+            topic,
+            file,
+            keep_empty,
+            keep_target,
+        } => {
+            manager.remove_config(&topic, &file, keep_empty, keep_target)?;
+        }
+        Commands::Version { .. }
+        | Commands::Schema
+        | Commands::ImportStow { .. }
+        | Commands::Completions { .. } => {
+            unreachable!("handled before loading the manager")
+        }
     }
 
+    if fail_on_warning && manager.warning_count() > 0 {
+        anyhow::bail!(
+            "{} warning(s) printed (--fail-on-warning)",
+            manager.warning_count()
+        );
+    }
     Ok(())
 }
@@ -0,0 +1,103 @@
+use crate::dotfile_manager::InstallOutcome;
+use crate::fsutil::write_atomic;
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::{fmt::Write, path::Path};
+
+/// File format for `install --report`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ReportFormat {
+    #[default]
+    Md,
+    Json,
+}
+
+/// Everything one install run did, mapping by mapping.
+#[derive(Serialize, Debug)]
+pub struct InstallReport {
+    pub generated: String,
+    pub dry_run: bool,
+    pub totals: Totals,
+    pub total_millis: f64,
+    pub mappings: Vec<MappingReport>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct MappingReport {
+    pub source: String,
+    #[serde(flatten)]
+    pub outcome: InstallOutcome,
+    pub millis: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Debug, Default, Clone, Copy)]
+pub struct Totals {
+    pub linked: usize,
+    pub backed_up: usize,
+    pub skipped: usize,
+    pub errors: usize,
+}
+
+impl InstallReport {
+    pub fn new(dry_run: bool) -> Self {
+        Self {
+            generated: chrono::Local::now().to_rfc3339(),
+            dry_run,
+            totals: Totals::default(),
+            total_millis: 0.0,
+            mappings: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, mapping: MappingReport) {
+        self.totals.linked += mapping.outcome.linked.len();
+        self.totals.backed_up += mapping.outcome.backed_up.len();
+        self.totals.skipped += mapping.outcome.skipped.len();
+        self.totals.errors += usize::from(mapping.error.is_some());
+        self.mappings.push(mapping);
+    }
+
+    pub fn write(&self, path: &Path, format: ReportFormat) -> Result<()> {
+        let contents = match format {
+            ReportFormat::Md => self.to_markdown(),
+            ReportFormat::Json => {
+                serde_json::to_string_pretty(self).context("Failed to serialize report")? + "\n"
+            }
+        };
+        write_atomic(path, contents.as_bytes())
+            .with_context(|| format!("Failed to write report {}", path.display()))
+    }
+
+    pub fn to_markdown(&self) -> String {
+        let totals = &self.totals;
+        let mut md = String::from("# Dotfiles install report\n\n");
+        // Writing to a String can't fail
+        let _ = writeln!(md, "Generated: {}", self.generated);
+        if self.dry_run {
+            md.push_str("Dry run: nothing was changed\n");
+        }
+        let _ = writeln!(
+            md,
+            "\n{} linked, {} backed up, {} unchanged, {} error(s) in {:.2}ms\n",
+            totals.linked, totals.backed_up, totals.skipped, totals.errors, self.total_millis
+        );
+
+        md.push_str("| Mapping | Linked | Backed up | Unchanged | Time (ms) | Error |\n");
+        md.push_str("|---|---|---|---|---|---|\n");
+        for mapping in &self.mappings {
+            let _ = writeln!(
+                md,
+                "| {} | {} | {} | {} | {:.2} | {} |",
+                mapping.source,
+                mapping.outcome.linked.len(),
+                mapping.outcome.backed_up.len(),
+                mapping.outcome.skipped.len(),
+                mapping.millis,
+                mapping.error.as_deref().unwrap_or("").replace('|', "\\|")
+            );
+        }
+        md
+    }
+}
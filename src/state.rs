@@ -0,0 +1,112 @@
+use crate::fsutil::write_atomic;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Links created by install, kept across runs so later commands can check
+/// them without re-reading the config.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct State {
+    /// When install last finished, as an RFC 3339 timestamp.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub installed_at: Option<String>,
+    /// Directories install created to hold targets, which `gc` may remove
+    /// once they are empty.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub created_dirs: Vec<PathBuf>,
+    #[serde(default)]
+    pub links: Vec<LinkRecord>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LinkRecord {
+    pub source: PathBuf,
+    pub target: PathBuf,
+}
+
+/// Backups taken during a single install run, stored inside that run's
+/// backup directory.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Manifest {
+    #[serde(default)]
+    pub backups: Vec<BackupRecord>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BackupRecord {
+    pub original: PathBuf,
+    pub backup: PathBuf,
+}
+
+/// Sidecar kept in a topic directory recording where each file added with
+/// `dotfiles add` originally lived.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct TopicMeta {
+    /// File name within the topic -> the path it was added from.
+    #[serde(default)]
+    pub files: BTreeMap<String, PathBuf>,
+}
+
+impl TopicMeta {
+    /// Name of the sidecar file inside a topic directory. Install never
+    /// links it.
+    pub const FILE_NAME: &'static str = ".dotfiles-meta.toml";
+
+    pub fn load(topic_dir: &Path) -> Result<Self> {
+        let path = topic_dir.join(Self::FILE_NAME);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let contents = fs::read_to_string(&path).context("Failed to read topic metadata")?;
+        toml::from_str(&contents).context("Failed to parse topic metadata")
+    }
+
+    pub fn save(&self, topic_dir: &Path) -> Result<()> {
+        let contents = toml::to_string(self).context("Failed to serialize topic metadata")?;
+        write_atomic(&topic_dir.join(Self::FILE_NAME), contents.as_bytes())
+            .context("Failed to write topic metadata")
+    }
+}
+
+impl State {
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let contents = fs::read_to_string(path).context("Failed to read state file")?;
+        toml::from_str(&contents).context("Failed to parse state file")
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let contents = toml::to_string(self).context("Failed to serialize state")?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).context("Failed to create state directory")?;
+        }
+        write_atomic(path, contents.as_bytes()).context("Failed to write state file")
+    }
+
+    /// Records a link, replacing any earlier record for the same target.
+    pub fn record_link(&mut self, source: &Path, target: &Path) {
+        self.links.retain(|link| link.target != target);
+        self.links.push(LinkRecord {
+            source: source.to_path_buf(),
+            target: target.to_path_buf(),
+        });
+    }
+}
+
+impl Manifest {
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = fs::read_to_string(path).context("Failed to read backup manifest")?;
+        toml::from_str(&contents).context("Failed to parse backup manifest")
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let contents = toml::to_string(self).context("Failed to serialize backup manifest")?;
+        write_atomic(path, contents.as_bytes()).context("Failed to write backup manifest")
+    }
+}
@@ -0,0 +1,147 @@
+use serde::Serialize;
+use std::{fs, path::Path};
+
+/// How an installed target relates to the source it should link to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TargetStatus {
+    /// The target is a symlink to the source.
+    Linked,
+    /// The target is a regular file holding exactly the expected content.
+    UpToDate,
+    /// Nothing exists at the target.
+    Missing,
+    /// The target is a regular file with exactly the source's content.
+    Adoptable,
+    /// The target is a symlink to somewhere other than the source.
+    WrongLink,
+    /// The target is a symlink that no longer resolves.
+    Broken,
+    /// The target is a regular file or directory that differs from the source.
+    Conflict,
+    /// Something already exists at a `skip_if_exists` target, so it is left
+    /// to the user.
+    Present,
+}
+
+impl TargetStatus {
+    /// Whether the target already matches what install would produce.
+    pub fn is_installed(&self) -> bool {
+        matches!(
+            self,
+            TargetStatus::Linked | TargetStatus::UpToDate | TargetStatus::Present
+        )
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            TargetStatus::Linked => "linked",
+            TargetStatus::UpToDate => "up to date",
+            TargetStatus::Missing => "not installed",
+            TargetStatus::Adoptable => "adoptable",
+            TargetStatus::WrongLink => "linked elsewhere",
+            TargetStatus::Broken => "broken link",
+            TargetStatus::Conflict => "conflict",
+            TargetStatus::Present => "present (not managed)",
+        }
+    }
+}
+
+pub fn classify(source: &Path, target: &Path) -> TargetStatus {
+    let Ok(metadata) = fs::symlink_metadata(target) else {
+        return TargetStatus::Missing;
+    };
+
+    if metadata.file_type().is_symlink() {
+        if !target.exists() {
+            return TargetStatus::Broken;
+        }
+        return if links_to(target, source) {
+            TargetStatus::Linked
+        } else {
+            TargetStatus::WrongLink
+        };
+    }
+
+    if metadata.is_file() && files_identical(source, target) {
+        TargetStatus::Adoptable
+    } else {
+        TargetStatus::Conflict
+    }
+}
+
+/// Like [`classify`], but never reads file contents. Plain files count as up
+/// to date when `copy` is set (they are meant to be copies) and as conflicts
+/// otherwise, so identical files aren't recognised as adoptable.
+pub fn classify_fast(source: &Path, target: &Path, copy: bool) -> TargetStatus {
+    match fs::symlink_metadata(target) {
+        Err(_) => TargetStatus::Missing,
+        Ok(metadata) if metadata.file_type().is_symlink() => classify(source, target),
+        Ok(metadata) if copy && metadata.is_file() => TargetStatus::UpToDate,
+        Ok(_) => TargetStatus::Conflict,
+    }
+}
+
+/// Per-category target counts, for prompts and scripts.
+#[derive(Serialize, Debug, Default)]
+pub struct StatusSummary {
+    pub installed: usize,
+    pub missing: usize,
+    pub drifted: usize,
+}
+
+impl StatusSummary {
+    pub fn add(&mut self, status: TargetStatus) {
+        match status {
+            TargetStatus::Linked | TargetStatus::UpToDate | TargetStatus::Present => {
+                self.installed += 1
+            }
+            TargetStatus::Missing => self.missing += 1,
+            _ => self.drifted += 1,
+        }
+    }
+
+    /// A single word describing overall health: `ok`, `drift` or `missing`.
+    pub fn token(&self) -> &'static str {
+        if self.drifted > 0 {
+            "drift"
+        } else if self.missing > 0 {
+            "missing"
+        } else {
+            "ok"
+        }
+    }
+}
+
+/// Whether `target` is a symlink to `source`. Paths are also compared after
+/// canonicalizing both sides, so links made through a symlinked dotfiles
+/// directory still count.
+pub fn links_to(target: &Path, source: &Path) -> bool {
+    match fs::read_link(target) {
+        Ok(points_to) if points_to == source => true,
+        Ok(_) => match (fs::canonicalize(target), fs::canonicalize(source)) {
+            (Ok(target), Ok(source)) => target == source,
+            _ => false,
+        },
+        Err(_) => false,
+    }
+}
+
+/// Classifies a target that should be a plain file with `expected` content,
+/// such as a rendered template.
+pub fn classify_copy(expected: &[u8], target: &Path) -> TargetStatus {
+    match fs::symlink_metadata(target) {
+        Err(_) => TargetStatus::Missing,
+        Ok(metadata) if metadata.is_file() => match fs::read(target) {
+            Ok(contents) if contents == expected => TargetStatus::UpToDate,
+            _ => TargetStatus::Conflict,
+        },
+        Ok(_) => TargetStatus::Conflict,
+    }
+}
+
+pub fn files_identical(a: &Path, b: &Path) -> bool {
+    match (fs::read(a), fs::read(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
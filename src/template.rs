@@ -0,0 +1,26 @@
+use anyhow::Result;
+
+/// Expands `{{ name }}` placeholders using `lookup`. Unknown names and
+/// unterminated placeholders are errors rather than being left in the output.
+pub fn render(input: &str, lookup: impl Fn(&str) -> Option<String>) -> Result<String> {
+    let mut output = String::with_capacity(input.len());
+    let mut rest = input;
+
+    while let Some(start) = rest.find("{{") {
+        output.push_str(&rest[..start]);
+        let after_open = &rest[start + 2..];
+        let Some(end) = after_open.find("}}") else {
+            anyhow::bail!("Unterminated template placeholder (missing `}}}}`)");
+        };
+
+        let name = after_open[..end].trim();
+        match lookup(name) {
+            Some(value) => output.push_str(&value),
+            None => anyhow::bail!("Undefined template variable `{}`", name),
+        }
+        rest = &after_open[end + 2..];
+    }
+
+    output.push_str(rest);
+    Ok(output)
+}
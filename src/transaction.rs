@@ -0,0 +1,83 @@
+use anyhow::Result;
+
+type Undo = Box<dyn FnOnce() -> Result<()>>;
+
+/// Filesystem changes made during one install run, each with a way to undo
+/// it, so a failed `--transactional` install can put everything back.
+#[derive(Default)]
+pub struct Transaction {
+    undo: Vec<(String, Undo)>,
+}
+
+impl Transaction {
+    /// Records a change that has just been made. `description` names it in
+    /// rollback errors.
+    pub fn record(&mut self, description: String, undo: impl FnOnce() -> Result<()> + 'static) {
+        self.undo.push((description, Box::new(undo)));
+    }
+
+    pub fn len(&self) -> usize {
+        self.undo.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.undo.is_empty()
+    }
+
+    /// Keeps every change.
+    pub fn commit(self) {}
+
+    /// Undoes every change, newest first. Undo steps that fail are skipped
+    /// and returned so the rest still run.
+    pub fn rollback(self) -> Vec<(String, anyhow::Error)> {
+        let mut failures = Vec::new();
+        for (description, undo) in self.undo.into_iter().rev() {
+            if let Err(err) = undo() {
+                failures.push((description, err));
+            }
+        }
+        failures
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    fn recording(
+        log: &Rc<RefCell<Vec<&'static str>>>,
+        name: &'static str,
+    ) -> impl FnOnce() -> Result<()> {
+        let log = Rc::clone(log);
+        move || {
+            log.borrow_mut().push(name);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn rollback_undoes_newest_first_and_keeps_going() {
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let mut transaction = Transaction::default();
+        transaction.record("first".into(), recording(&log, "first"));
+        transaction.record("broken".into(), || anyhow::bail!("gone"));
+        transaction.record("last".into(), recording(&log, "last"));
+        assert_eq!(transaction.len(), 3);
+
+        let failures = transaction.rollback();
+        assert_eq!(*log.borrow(), ["last", "first"]);
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].0, "broken");
+    }
+
+    #[test]
+    fn commit_runs_no_undo() {
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let mut transaction = Transaction::default();
+        transaction.record("first".into(), recording(&log, "first"));
+        transaction.commit();
+        assert!(log.borrow().is_empty());
+    }
+}